@@ -4,6 +4,7 @@ dofile(BASE_DIR.."commands/Commands.lua")
 dofile(BASE_DIR.."actions/Actions.lua")
 dofile(BASE_DIR.."events/Events.lua")
 dofile(BASE_DIR.."items/Items.lua")
+dofile(BASE_DIR.."battlenpcs/BattleNpcs.lua")
 dofile(BASE_DIR.."Global.lua")
 
 -- Lua error handlers, and other server events like player login
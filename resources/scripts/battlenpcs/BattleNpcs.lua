@@ -0,0 +1,4 @@
+-- Battle NPCs are registered by their BNpcBase id, and every battle NPC using it shares the script.
+-- Please keep these ids sorted!
+
+registerBattleNpc(13498, "battlenpcs/DebugMonster.lua")
@@ -0,0 +1,21 @@
+-- The monster spawned by !spawnmonster
+-- Every function here is optional, anything left out uses the server's defaults.
+
+-- How it behaves in combat, distances are in yalms and times are in seconds
+function getBehaviour()
+    return {
+        aggro_range = 10.0,
+        leash_range = 40.0,
+        attack_range = 3.0,
+        speed = 6.0,
+        auto_attack_interval = 3.0,
+    }
+end
+
+-- What its auto-attacks do to its target
+function doAutoAttack()
+    effects = EffectsBuilder()
+    effects:damage("Normal", "Blunt", "Unaspected", 5)
+
+    return effects
+end
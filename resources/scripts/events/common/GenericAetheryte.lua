@@ -30,10 +30,10 @@ function onReturn(scene, results, player)
 
     if scene == SCENE_SHOW_MENU then -- main aetheryte prompt scene
         if menu_option == SET_HOME_POINT then
-            -- TODO: logic for setting home point
-            --[[ if decision == HOME_PNT_YES then
-            else
-            end ]]
+            if decision == HOME_PNT_YES then
+                -- the lower bits of the event id are the aetheryte id
+                player:set_homepoint(EVENT_ID & 0xFFFF)
+            end
         elseif menu_option == AETHERNET_SUBMENU then
             if decision ~= AETHERNET_SUBMENU_CANCEL then
                 player:finish_event(EVENT_ID) -- Need to finish the event here, because warping does not return to this callback (the game will crash or softlock otherwise)
@@ -1,3 +1,5 @@
+use std::collections::HashMap;
+
 use kawari::RECEIVE_BUFFER_SIZE;
 use kawari::common::GameData;
 use kawari::config::get_config;
@@ -8,6 +10,7 @@ use kawari::ipc::lobby::ServiceAccount;
 use kawari::ipc::lobby::{ClientLobbyIpcData, ServerLobbyIpcSegment};
 use kawari::lobby::LobbyConnection;
 use kawari::lobby::send_custom_world_packet;
+use kawari::lobby::watch_world_status;
 use kawari::packet::ConnectionType;
 use kawari::packet::oodle::OodleNetwork;
 use kawari::packet::{PacketState, SegmentData, send_keep_alive};
@@ -27,9 +30,16 @@ async fn main() {
     tracing::info!("Server started on {addr}");
 
     let mut game_data = GameData::new();
-    let world_name = game_data
-        .get_world_name(config.world.world_id)
-        .expect("Unknown world name");
+    let mut worlds = config.get_worlds();
+    for world in &mut worlds {
+        if world.name.is_empty() {
+            world.name = game_data
+                .get_world_name(world.id)
+                .expect("Unknown world name");
+        }
+    }
+
+    tokio::spawn(watch_world_status());
 
     loop {
         let (socket, _) = listener.accept().await.unwrap();
@@ -45,7 +55,9 @@ async fn main() {
             state,
             session_id: None,
             stored_character_creation_name: String::new(),
-            world_name: world_name.clone(),
+            stored_character_creation_world: 0,
+            worlds: worlds.clone(),
+            character_worlds: HashMap::new(),
             service_accounts: Vec::new(),
             selected_service_account: None,
         };
@@ -123,10 +135,26 @@ async fn main() {
                                         connection.send_error(*sequence, 1012, 13101).await;
                                     }
                                 }
-                                ClientLobbyIpcData::ServiceLogin { sequence } => {
-                                    // TODO: support selecting a service account
-                                    connection.selected_service_account =
-                                        Some(connection.service_accounts[0].id);
+                                ClientLobbyIpcData::ServiceLogin {
+                                    account_index,
+                                    sequence,
+                                } => {
+                                    let Some(service_account) =
+                                        connection.service_accounts.get(*account_index as usize)
+                                    else {
+                                        tracing::warn!(
+                                            "Client selected service account {account_index}, which doesn't exist!"
+                                        );
+                                        connection.send_error(*sequence, 1012, 13101).await;
+                                        break;
+                                    };
+
+                                    tracing::info!(
+                                        "Client selected service account {}",
+                                        service_account.name
+                                    );
+
+                                    connection.selected_service_account = Some(service_account.id);
                                     connection.send_lobby_info(*sequence).await
                                 }
                                 ClientLobbyIpcData::CharaMake(character_action) => {
@@ -141,35 +169,68 @@ async fn main() {
                                 } => {
                                     tracing::info!("Client is joining the world with {content_id}");
 
-                                    let our_actor_id;
+                                    let Some(world) = connection
+                                        .character_worlds
+                                        .get(content_id)
+                                        .and_then(|world_id| {
+                                            connection
+                                                .worlds
+                                                .iter()
+                                                .find(|world| world.id == *world_id)
+                                        })
+                                        .cloned()
+                                    else {
+                                        tracing::warn!(
+                                            "Couldn't find which world {content_id} lives on!"
+                                        );
+                                        connection.send_error(*sequence, 1012, 13101).await;
+                                        break;
+                                    };
 
                                     // find the actor id for this content id
                                     // NOTE: This is NOT the ideal solution. I theorize the lobby server has it's own records with this information.
+                                    let ipc_segment = CustomIpcSegment {
+                                        op_code: CustomIpcType::GetActorId,
+                                        data: CustomIpcData::GetActorId {
+                                            content_id: *content_id,
+                                        },
+                                        ..Default::default()
+                                    };
+
+                                    let our_actor_id = match send_custom_world_packet(
+                                        world.id,
+                                        ipc_segment,
+                                    )
+                                    .await
                                     {
-                                        let ipc_segment = CustomIpcSegment {
-                                            unk1: 0,
-                                            unk2: 0,
-                                            op_code: CustomIpcType::GetActorId,
-                                            option: 0,
-                                            timestamp: 0,
-                                            data: CustomIpcData::GetActorId {
-                                                content_id: *content_id,
-                                            },
-                                        };
-
-                                        let response_segment =
-                                            send_custom_world_packet(ipc_segment).await.unwrap();
-
-                                        match &response_segment.data {
-                                            CustomIpcData::ActorIdFound { actor_id } => {
-                                                our_actor_id = *actor_id;
-                                            }
-                                            _ => panic!("Unexpected custom IPC packet type here!"),
+                                        Ok(CustomIpcSegment {
+                                            data: CustomIpcData::ActorIdFound { actor_id },
+                                            ..
+                                        }) => actor_id,
+                                        Ok(_) => {
+                                            tracing::warn!(
+                                                "World {} sent an unexpected response!",
+                                                world.id
+                                            );
+                                            connection.send_error(*sequence, 1012, 13101).await;
+                                            break;
                                         }
-                                    }
+                                        Err(err) => {
+                                            tracing::warn!(
+                                                "Couldn't find the actor id of {content_id}: {err}"
+                                            );
+                                            connection.send_error(*sequence, 1012, 13101).await;
+                                            break;
+                                        }
+                                    };
 
                                     connection
-                                        .send_enter_world(*sequence, *content_id, our_actor_id)
+                                        .send_enter_world(
+                                            *sequence,
+                                            *content_id,
+                                            our_actor_id,
+                                            &world,
+                                        )
                                         .await;
                                 }
                                 _ => {}
@@ -1,7 +1,7 @@
 use std::sync::Arc;
 
 use axum::extract::{Multipart, Query, State};
-use axum::response::{Html, Redirect};
+use axum::response::{Html, IntoResponse, Redirect, Response};
 use axum::routing::post;
 use axum::{Form, Router, routing::get};
 use axum_extra::extract::CookieJar;
@@ -9,7 +9,9 @@ use axum_extra::extract::cookie::{Cookie, Expiration};
 use kawari::config::get_config;
 use kawari::ipc::kawari::{CustomIpcData, CustomIpcSegment, CustomIpcType};
 use kawari::lobby::send_custom_world_packet;
-use kawari::login::{LoginDatabase, LoginError};
+use kawari::login::{
+    LoginDatabase, LoginError, SERVICE_ACCOUNT_NAME_MAX_LENGTH, ServiceAccountNameError,
+};
 use minijinja::{Environment, context};
 use serde::Deserialize;
 use tower_http::cors::{Any, CorsLayer};
@@ -59,6 +61,12 @@ fn setup_default_environment() -> Environment<'static> {
             .expect("Failed to find template!"),
     )
     .unwrap();
+    env.add_template_owned(
+        "cancelaccount.html",
+        std::fs::read_to_string("resources/templates/cancelaccount.html")
+            .expect("Failed to find template!"),
+    )
+    .unwrap();
     env.add_template_owned(
         "restore.html",
         std::fs::read_to_string("resources/templates/restore.html")
@@ -234,47 +242,187 @@ async fn do_login(
 }
 
 async fn account(State(state): State<LoginServerState>, jar: CookieJar) -> Html<String> {
-    if let Some(session_id) = jar.get("cis_sessid") {
-        let user_id = state.database.get_user_id(session_id.value());
-        let username = state.database.get_username(user_id);
-
-        let environment = setup_default_environment();
-        let template = environment.get_template("account.html").unwrap();
-        Html(template.render(context! { username => username }).unwrap())
+    if let Some(user_id) = logged_in_user(&state, &jar) {
+        render_account(&state, user_id, None)
     } else {
         Html("You need to be logged in!".to_string())
     }
 }
 
+/// Renders the service account management page, optionally with an error from the last thing the user tried.
+fn render_account(state: &LoginServerState, user_id: u32, error: Option<&str>) -> Html<String> {
+    let username = state.database.get_username(user_id);
+    let service_accounts = state.database.get_service_accounts(user_id);
+
+    let environment = setup_default_environment();
+    let template = environment.get_template("account.html").unwrap();
+    Html(
+        template
+            .render(context! {
+                username => username,
+                can_remove => service_accounts.len() > 1,
+                service_accounts => service_accounts,
+                error => error,
+            })
+            .unwrap(),
+    )
+}
+
+async fn add_service_account(State(state): State<LoginServerState>, jar: CookieJar) -> Redirect {
+    if let Some(user_id) = logged_in_user(&state, &jar) {
+        if state.database.add_service_account(user_id).is_none() {
+            tracing::warn!("Account {user_id} already has the maximum number of service accounts!");
+        }
+    }
+
+    Redirect::to("/account/app/svc/manage")
+}
+
+#[derive(Deserialize, Debug)]
+struct RenameServiceAccountInput {
+    service_account: u32,
+    name: String,
+}
+
+async fn rename_service_account(
+    State(state): State<LoginServerState>,
+    jar: CookieJar,
+    Form(input): Form<RenameServiceAccountInput>,
+) -> Response {
+    let Some(user_id) = logged_in_user(&state, &jar) else {
+        return Html("You need to be logged in!".to_string()).into_response();
+    };
+
+    let error = match state.database.rename_service_account(
+        user_id,
+        input.service_account,
+        input.name.trim(),
+    ) {
+        Ok(()) => return Redirect::to("/account/app/svc/manage").into_response(),
+        Err(ServiceAccountNameError::Empty) => "Service account names can't be empty.".to_string(),
+        Err(ServiceAccountNameError::TooLong) => format!(
+            "Service account names can't be longer than {SERVICE_ACCOUNT_NAME_MAX_LENGTH} bytes."
+        ),
+        Err(ServiceAccountNameError::Taken) => {
+            "Another one of your service accounts already has that name.".to_string()
+        }
+    };
+
+    render_account(&state, user_id, Some(&error)).into_response()
+}
+
+#[derive(Deserialize, Debug)]
+struct RemoveServiceAccountInput {
+    service_account: u32,
+}
+
+async fn remove_service_account(
+    State(state): State<LoginServerState>,
+    jar: CookieJar,
+    Form(input): Form<RemoveServiceAccountInput>,
+) -> Redirect {
+    if let Some(user_id) = logged_in_user(&state, &jar) {
+        // everyone needs at least one, otherwise they can't get into the lobby
+        if state
+            .database
+            .owns_service_account(user_id, input.service_account)
+            && state.database.get_service_accounts(user_id).len() > 1
+            && delete_world_characters(input.service_account).await
+        {
+            state
+                .database
+                .remove_service_account(user_id, input.service_account);
+        }
+    }
+
+    Redirect::to("/account/app/svc/manage")
+}
+
+/// Asks every world server to delete the characters belonging to a service account, even if some of them fail. Returns false if any of them failed.
+/// Worlds that already deleted them have nothing left to delete, so it's safe to call again to retry.
+async fn delete_world_characters(service_account_id: u32) -> bool {
+    let config = get_config();
+
+    let mut deleted_everywhere = true;
+    for world in config.get_worlds() {
+        let ipc_segment = CustomIpcSegment {
+            op_code: CustomIpcType::DeleteServiceAccount,
+            data: CustomIpcData::DeleteServiceAccount { service_account_id },
+            ..Default::default()
+        };
+
+        match send_custom_world_packet(world.id, ipc_segment).await {
+            Ok(CustomIpcSegment {
+                data: CustomIpcData::ServiceAccountDeleted { .. },
+                ..
+            }) => {}
+            Ok(_) => {
+                tracing::warn!("World {} sent an unexpected response!", world.id);
+                deleted_everywhere = false;
+            }
+            Err(err) => {
+                tracing::warn!(
+                    "Failed to delete the characters of service account {service_account_id} on world {}: {err}",
+                    world.id
+                );
+                deleted_everywhere = false;
+            }
+        }
+    }
+
+    deleted_everywhere
+}
+
 async fn upload_character_backup(
     State(state): State<LoginServerState>,
     jar: CookieJar,
     mut multipart: Multipart,
 ) -> Redirect {
-    if let Some(session_id) = jar.get("cis_sessid") {
-        let user_id = state.database.get_user_id(session_id.value());
-        let service_account_id = state.database.get_service_account(user_id);
+    if let Some(user_id) = logged_in_user(&state, &jar) {
+        let mut service_account_id = None;
+        let mut world_id = None;
+        let mut backup = None;
 
         while let Some(field) = multipart.next_field().await.unwrap() {
             let name = field.name().unwrap().to_string();
             let data = field.bytes().await.unwrap();
 
-            std::fs::write("temp.zip", data).unwrap();
-
-            if name == "charbak" {
-                let ipc_segment = CustomIpcSegment {
-                    unk1: 0,
-                    unk2: 0,
-                    op_code: CustomIpcType::ImportCharacter,
-                    option: 0,
-                    timestamp: 0,
-                    data: CustomIpcData::ImportCharacter {
-                        service_account_id,
-                        path: "temp.zip".to_string(),
-                    },
-                };
-
-                send_custom_world_packet(ipc_segment).await.unwrap();
+            match name.as_str() {
+                "service_account" => {
+                    service_account_id = String::from_utf8_lossy(&data).parse::<u32>().ok()
+                }
+                "world" => world_id = String::from_utf8_lossy(&data).parse::<u16>().ok(),
+                "charbak" => backup = Some(data),
+                _ => {}
+            }
+        }
+
+        if let (Some(service_account_id), Some(world_id), Some(backup)) =
+            (service_account_id, world_id, backup)
+        {
+            if !state
+                .database
+                .owns_service_account(user_id, service_account_id)
+            {
+                tracing::warn!(
+                    "Account {user_id} tried to restore a backup to service account {service_account_id}, which isn't theirs!"
+                );
+                return Redirect::to("/account/app/svc/manage");
+            }
+
+            std::fs::write("temp.zip", backup).unwrap();
+
+            let ipc_segment = CustomIpcSegment {
+                op_code: CustomIpcType::ImportCharacter,
+                data: CustomIpcData::ImportCharacter {
+                    service_account_id,
+                    path: "temp.zip".to_string(),
+                },
+                ..Default::default()
+            };
+
+            if let Err(err) = send_custom_world_packet(world_id, ipc_segment).await {
+                tracing::warn!("Failed to import character backup on world {world_id}: {err}");
             }
         }
     }
@@ -282,31 +430,186 @@ async fn upload_character_backup(
     Redirect::to("/account/app/svc/manage")
 }
 
-async fn logout(jar: CookieJar) -> (CookieJar, Redirect) {
+async fn logout(State(state): State<LoginServerState>, jar: CookieJar) -> (CookieJar, Redirect) {
     let config = get_config();
-    // TODO: remove session from database
+    if let Some(session_id) = jar.get("cis_sessid") {
+        state.database.revoke_session(session_id.value());
+    }
     (
         jar.remove("cis_sessid"),
         Redirect::to(&format!("http://{}/", config.web.server_name)),
     )
 }
 
-async fn change_password() -> Html<String> {
-    // TODO: actually change password
+/// Finds the user that's currently logged in, if any.
+fn logged_in_user(state: &LoginServerState, jar: &CookieJar) -> Option<u32> {
+    jar.get("cis_sessid")
+        .and_then(|session_id| state.database.get_user_id(session_id.value()))
+}
+
+async fn change_password(State(state): State<LoginServerState>, jar: CookieJar) -> Html<String> {
+    let Some(user_id) = logged_in_user(&state, &jar) else {
+        return Html("You need to be logged in!".to_string());
+    };
+
+    let environment = setup_default_environment();
+    let template = environment.get_template("changepassword.html").unwrap();
+    Html(
+        template
+            .render(context! { username => state.database.get_username(user_id) })
+            .unwrap(),
+    )
+}
+
+#[derive(Deserialize, Debug)]
+struct ChangePasswordInput {
+    old_password: String,
+    new_password: String,
+}
+
+async fn do_change_password(
+    State(state): State<LoginServerState>,
+    jar: CookieJar,
+    Form(input): Form<ChangePasswordInput>,
+) -> (CookieJar, Html<String>) {
+    let Some(user_id) = logged_in_user(&state, &jar) else {
+        return (jar, Html("You need to be logged in!".to_string()));
+    };
+
+    let username = state.database.get_username(user_id);
     let environment = setup_default_environment();
     let template = environment.get_template("changepassword.html").unwrap();
-    Html(template.render(context! {}).unwrap())
+
+    if !state.database.check_password(user_id, &input.old_password) {
+        return (
+            jar,
+            Html(
+                template
+                    .render(context! { username => username, error => "Your old password is incorrect." })
+                    .unwrap(),
+            ),
+        );
+    }
+
+    if input.new_password.is_empty() {
+        return (
+            jar,
+            Html(
+                template
+                    .render(context! { username => username, error => "Your new password can't be empty." })
+                    .unwrap(),
+            ),
+        );
+    }
+
+    // this logs them out everywhere, so give this browser a new session
+    state.database.change_password(user_id, &input.new_password);
+    let Some(sid) = state.database.create_session(user_id) else {
+        return (
+            jar.remove("cis_sessid"),
+            Html("Failed to create a new session, please log in again.".to_string()),
+        );
+    };
+
+    let cookie = Cookie::build(("cis_sessid", sid))
+        .path("/")
+        .secure(false)
+        .expires(Expiration::Session)
+        .http_only(true);
+
+    (
+        jar.add(cookie),
+        Html(
+            template
+                .render(
+                    context! { username => username, message => "Your password has been changed." },
+                )
+                .unwrap(),
+        ),
+    )
+}
+
+async fn cancel_account(State(state): State<LoginServerState>, jar: CookieJar) -> Html<String> {
+    let Some(user_id) = logged_in_user(&state, &jar) else {
+        return Html("You need to be logged in!".to_string());
+    };
+
+    let environment = setup_default_environment();
+    let template = environment.get_template("cancelaccount.html").unwrap();
+    Html(
+        template
+            .render(context! { username => state.database.get_username(user_id) })
+            .unwrap(),
+    )
 }
 
-async fn cancel_account(jar: CookieJar) -> (CookieJar, Redirect) {
-    // TODO: actually delete account
-    (jar.remove("cis_sessid"), Redirect::to("/"))
+#[derive(Deserialize, Debug)]
+struct CancelAccountInput {
+    password: String,
 }
 
-async fn restore_backup() -> Html<String> {
+async fn do_cancel_account(
+    State(state): State<LoginServerState>,
+    jar: CookieJar,
+    Form(input): Form<CancelAccountInput>,
+) -> Result<(CookieJar, Redirect), Html<String>> {
+    let Some(user_id) = logged_in_user(&state, &jar) else {
+        return Err(Html("You need to be logged in!".to_string()));
+    };
+
+    let username = state.database.get_username(user_id);
+    let environment = setup_default_environment();
+    let template = environment.get_template("cancelaccount.html").unwrap();
+
+    if !state.database.check_password(user_id, &input.password) {
+        return Err(Html(
+            template
+                .render(context! { username => username, error => "Your password is incorrect." })
+                .unwrap(),
+        ));
+    }
+
+    // characters live on the world server, so they have to be deleted there first
+    // try every service account even if one fails, deleting again is harmless so the user can simply retry
+    let mut deleted_everywhere = true;
+    for service_account in state.database.get_service_accounts(user_id) {
+        deleted_everywhere &= delete_world_characters(service_account.id).await;
+    }
+
+    if !deleted_everywhere {
+        tracing::warn!("Not deleting account {user_id}, since some of its characters still exist!");
+        return Err(Html(
+            template
+                .render(context! { username => username, error => "Failed to delete your characters, please try again later." })
+                .unwrap(),
+        ));
+    }
+
+    state.database.delete_user(user_id);
+
+    let config = get_config();
+    Ok((
+        jar.remove("cis_sessid"),
+        Redirect::to(&format!("http://{}/", config.web.server_name)),
+    ))
+}
+
+async fn restore_backup(State(state): State<LoginServerState>, jar: CookieJar) -> Html<String> {
+    let Some(user_id) = logged_in_user(&state, &jar) else {
+        return Html("You need to be logged in!".to_string());
+    };
+
     let environment = setup_default_environment();
     let template = environment.get_template("restore.html").unwrap();
-    Html(template.render(context! {}).unwrap())
+    Html(
+        template
+            .render(context! {
+                username => state.database.get_username(user_id),
+                service_accounts => state.database.get_service_accounts(user_id),
+                worlds => get_config().get_worlds(),
+            })
+            .unwrap(),
+    )
 }
 
 #[tokio::main]
@@ -332,9 +635,23 @@ async fn main() {
         .route("/oauth/oa/registligt", get(register))
         .route("/oauth/oa/registlist", post(do_register))
         .route("/account/app/svc/manage", get(account))
+        .route(
+            "/account/app/svc/addServiceAccount",
+            post(add_service_account),
+        )
+        .route(
+            "/account/app/svc/renameServiceAccount",
+            post(rename_service_account),
+        )
+        .route(
+            "/account/app/svc/removeServiceAccount",
+            post(remove_service_account),
+        )
         .route("/account/app/svc/logout", get(logout))
         .route("/account/app/svc/mbrPasswd", get(change_password))
+        .route("/account/app/svc/mbrPasswd", post(do_change_password))
         .route("/account/app/svc/mbrCancel", get(cancel_account))
+        .route("/account/app/svc/mbrCancel", post(do_cancel_account))
         .route("/account/app/svc/restore", get(restore_backup))
         .route("/account/app/svc/restore", post(upload_character_backup))
         .with_state(state)
@@ -5,9 +5,9 @@ use std::time::{Duration, Instant};
 use kawari::RECEIVE_BUFFER_SIZE;
 use kawari::common::Position;
 use kawari::common::{GameData, timestamp_secs};
-use kawari::config::get_config;
+use kawari::config::{check_internal_secret, get_config};
 use kawari::inventory::Item;
-use kawari::ipc::chat::{ServerChatIpcData, ServerChatIpcSegment};
+use kawari::ipc::chat::{ChatChannel, ClientChatIpcData, ServerChatIpcSegment};
 use kawari::ipc::zone::{
     ActorControlCategory, ActorControlSelf, PlayerEntry, PlayerSpawn, PlayerStatus, SocialList,
 };
@@ -15,17 +15,20 @@ use kawari::ipc::zone::{
     ClientTriggerCommand, ClientZoneIpcData, EventStart, GameMasterRank, OnlineStatus,
     ServerZoneIpcData, ServerZoneIpcSegment, SocialListRequestType,
 };
-use kawari::opcodes::{ServerChatIpcType, ServerZoneIpcType};
+use kawari::opcodes::ServerZoneIpcType;
 use kawari::packet::oodle::OodleNetwork;
 use kawari::packet::{
-    ConnectionType, PacketSegment, PacketState, SegmentData, SegmentType, send_keep_alive,
+    ConnectionType, PacketHeader, PacketSegment, PacketState, SegmentData, SegmentType,
+    send_keep_alive,
 };
-use kawari::world::{ChatHandler, ExtraLuaState, LuaZone, Zone, ZoneConnection, load_init_script};
 use kawari::world::{
-    ClientHandle, Event, FromServer, LuaPlayer, PlayerData, ServerHandle, StatusEffects, ToServer,
-    WorldDatabase, handle_custom_ipc, server_main_loop,
+    ActorIdAllocator, ChatConnection, ClientHandle, Event, FromServer, InternalConnection,
+    LuaPlayer, PlayerData, RconCommand, ServerHandle, StatusEffects, ToServer, WorldDatabase,
+    handle_custom_ipc, server_main_loop,
 };
+use kawari::world::{ChatHandler, ExtraLuaState, LuaZone, Zone, ZoneConnection, load_init_script};
 
+use binrw::BinRead;
 use mlua::{Function, Lua};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::join;
@@ -34,7 +37,15 @@ use tokio::sync::mpsc::{Receiver, UnboundedReceiver, UnboundedSender, channel, u
 use tokio::sync::oneshot;
 use tokio::task::JoinHandle;
 
-fn spawn_main_loop() -> (ServerHandle, JoinHandle<()>) {
+/// How long to wait for everyone to save and disconnect when shutting down.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long a new connection has to send its first packet header, before it's dropped.
+const FIRST_PACKET_TIMEOUT: Duration = Duration::from_secs(10);
+
+fn spawn_main_loop(
+    actor_ids: Arc<ActorIdAllocator>,
+    lua: Arc<Mutex<Lua>>,
+) -> (ServerHandle, JoinHandle<()>) {
     let (send, recv) = channel(64);
 
     let handle = ServerHandle {
@@ -43,7 +54,7 @@ fn spawn_main_loop() -> (ServerHandle, JoinHandle<()>) {
     };
 
     let join = tokio::spawn(async move {
-        let res = server_main_loop(recv).await;
+        let res = server_main_loop(recv, actor_ids, lua).await;
         match res {
             Ok(()) => {}
             Err(err) => {
@@ -124,9 +135,6 @@ async fn client_loop(
 
     let mut lua_player = LuaPlayer::default();
 
-    // TODO: this is terrible, just have a separate zone/chat connection
-    let mut is_zone_connection = false;
-
     let mut buf = vec![0; RECEIVE_BUFFER_SIZE];
     loop {
         tokio::select! {
@@ -146,7 +154,7 @@ async fn client_loop(
                         if n > 0 {
                             connection.last_keep_alive = Instant::now();
 
-                            let (segments, connection_type) = connection.parse_packet(&buf[..n]);
+                            let (segments, _) = connection.parse_packet(&buf[..n]);
                             for segment in &segments {
                                 match &segment.data {
                                     SegmentData::None() => {},
@@ -157,69 +165,22 @@ async fn client_loop(
                                         // initialize player data if it doesn't exist'
                                         if connection.player_data.actor_id == 0 {
                                             connection.player_data = database.find_player_data(actor_id);
+                                            connection.saved_player_data = connection.player_data.clone();
                                         }
 
-                                        if connection_type == ConnectionType::Zone {
-                                            is_zone_connection = true;
-
-                                            // collect actor data
-                                            connection.initialize(actor_id).await;
-
-                                            connection.exit_position = Some(connection.player_data.position);
-                                            connection.exit_rotation = Some(connection.player_data.rotation);
+                                        // collect actor data
+                                        connection.initialize(actor_id).await;
 
-                                            let mut client_handle = client_handle.clone();
-                                            client_handle.actor_id = actor_id;
+                                        connection.exit_position = Some(connection.player_data.position);
+                                        connection.exit_rotation = Some(connection.player_data.rotation);
 
-                                            // tell the server we exist, now that we confirmed we are a legitimate connection
-                                            connection.handle.send(ToServer::NewClient(client_handle)).await;
-                                        } else if connection_type == ConnectionType::Chat {
-                                            // We have send THEM a keep alive
-                                            connection.send_chat_segment(PacketSegment {
-                                                segment_type: SegmentType::KeepAliveRequest,
-                                                data: SegmentData::KeepAliveRequest {
-                                                    id: 0xE0037603u32,
-                                                    timestamp: timestamp_secs(),
-                                                },
-                                                ..Default::default()
-                                            })
-                                            .await;
-
-                                            // initialize connection
-                                            connection.send_chat_segment(PacketSegment {
-                                                segment_type: SegmentType::Initialize,
-                                                data: SegmentData::Initialize {
-                                                    player_id: connection.player_data.actor_id,
-                                                    timestamp: timestamp_secs(),
-                                                },
-                                                ..Default::default()
-                                            })
-                                            .await;
+                                        let mut client_handle = client_handle.clone();
+                                        client_handle.actor_id = actor_id;
 
-                                            // we need the actor id at this point!
-                                            assert!(connection.player_data.actor_id != 0);
+                                        let name = database.find_chara_make(connection.player_data.content_id).name;
 
-                                            // send login reply
-                                            {
-                                                let ipc = ServerChatIpcSegment {
-                                                    op_code: ServerChatIpcType::LoginReply,
-                                                    timestamp: timestamp_secs(),
-                                                    data: ServerChatIpcData::LoginReply {
-                                                        timestamp: 0,
-                                                        sid: 0,
-                                                    },
-                                                    ..Default::default()
-                                                };
-
-                                                connection.send_chat_segment(PacketSegment {
-                                                    source_actor: connection.player_data.actor_id,
-                                                    target_actor: connection.player_data.actor_id,
-                                                    segment_type: SegmentType::Ipc,
-                                                    data: SegmentData::Ipc { data: ipc },
-                                                })
-                                                .await;
-                                            }
-                                        }
+                                        // tell the server we exist, now that we confirmed we are a legitimate connection
+                                        connection.handle.send(ToServer::NewClient(client_handle, name)).await;
                                     }
                                     SegmentData::Ipc { data } => {
                                         match &data.data {
@@ -339,7 +300,12 @@ async fn client_loop(
                                                 let common = connection.get_player_common_spawn(connection.exit_position, connection.exit_rotation);
 
                                                 // tell the server we loaded into the zone, so it can start sending us acors
-                                                connection.handle.send(ToServer::ZoneLoaded(connection.id, connection.zone.as_ref().unwrap().id, common.clone())).await;
+                                                connection.handle.send(ToServer::ZoneLoaded(connection.id, common.clone())).await;
+
+                                                // now that we know where they ended up, save in case they leave before moving
+                                                connection.player_data.position = common.pos;
+                                                connection.player_data.rotation = common.rotation;
+                                                connection.save_player_data();
 
                                                 let chara_details = database.find_chara_make(connection.player_data.content_id);
 
@@ -406,9 +372,13 @@ async fn client_loop(
                                                 connection.exit_rotation = None;
                                             }
                                             ClientZoneIpcData::ClientTrigger(trigger) => {
-                                                // store the query for scripts
-                                                if let ClientTriggerCommand::TeleportQuery { aetheryte_id } = trigger.trigger {
-                                                    connection.player_data.teleport_query.aetheryte_id = aetheryte_id as u16;
+                                                match trigger.trigger {
+                                                    // store the query for scripts
+                                                    ClientTriggerCommand::TeleportQuery { aetheryte_id } => {
+                                                        connection.player_data.teleport_query.aetheryte_id = aetheryte_id as u16;
+                                                    }
+                                                    ClientTriggerCommand::Revive { .. } => connection.return_to_homepoint().await,
+                                                    _ => {}
                                                 }
 
                                                 // inform the server of our trigger, it will handle sending it to other clients
@@ -501,12 +471,21 @@ async fn client_loop(
                                             ClientZoneIpcData::Disconnected { .. } => {
                                                 tracing::info!("Client disconnected!");
 
+                                                connection.save_player_data();
                                                 connection.handle.send(ToServer::Disconnected(connection.id)).await;
 
                                                 break;
                                             }
                                             ClientZoneIpcData::ChatMessage(chat_message) => {
-                                                connection.handle.send(ToServer::Message(connection.id, chat_message.message.clone())).await;
+                                                // commands aren't meant for anyone else to see
+                                                if !chat_message.message.starts_with('!') {
+                                                    match ChatChannel::from_id(chat_message.channel) {
+                                                        Some(channel) => {
+                                                            connection.handle.send(ToServer::Message(connection.id, connection.player_data.actor_id, channel, chat_message.message.clone())).await;
+                                                        }
+                                                        None => tracing::warn!("Unknown chat channel {}, dropping message!", chat_message.channel),
+                                                    }
+                                                }
 
                                                 let mut handled = false;
                                                 let command_trigger: char = '!';
@@ -950,7 +929,9 @@ async fn client_loop(
                                     SegmentData::KeepAliveResponse { .. } => {
                                         tracing::info!("Got keep alive response from client... cool...");
                                     }
-                                    SegmentData::KawariIpc { data } => handle_custom_ipc(&mut connection, data).await,
+                                    SegmentData::KawariIpc { .. } => {
+                                        tracing::warn!("{} sent custom IPC to the game port, ignoring it!", connection.ip);
+                                    }
                                     _ => {
                                         panic!("The server is recieving a response or unknown packet: {segment:#?}")
                                     }
@@ -1000,8 +981,35 @@ async fn client_loop(
                     FromServer::ActorControlSelf(actor_control) => connection.actor_control_self(actor_control).await,
                     FromServer::ActionComplete(request) => connection.execute_action(request, &mut lua_player).await,
                     FromServer::ActionCancelled() => connection.cancel_action().await,
+                    FromServer::ActionResult(actor_id, action_result) => connection.send_action_result(actor_id, action_result).await,
+                    FromServer::UpdateHpMp(actor_id, hp, mp) => connection.set_hp_mp(actor_id, hp, mp).await,
+                    FromServer::GainEffect(effect_id, duration, source_actor_id) => {
+                        connection.gain_effect(effect_id, duration, source_actor_id).await;
+                        lua_player.status_effects = connection.status_effects.clone();
+                    }
+                    FromServer::TickStatusEffects(elapsed) => {
+                        connection.tick_status_effects(elapsed).await;
+                        lua_player.status_effects = connection.status_effects.clone();
+                    }
+                    FromServer::ChangeWeather(weather_id) => connection.change_weather(weather_id).await,
                     FromServer::UpdateConfig(actor_id, config) => connection.update_config(actor_id, config).await,
                     FromServer::ActorEquip(actor_id, main_weapon_id, model_ids) => connection.update_equip(actor_id, main_weapon_id, model_ids).await,
+                    FromServer::ChangeZone(zone_id) => connection.load_zone(zone_id).await,
+                    FromServer::Teleport(zone_id) => connection.change_zone(zone_id).await,
+                    FromServer::SetGmRank(rank) => {
+                        connection.player_data.gm_rank = rank;
+                        connection.save_player_data();
+                        connection.send_message(&format!("Your GM rank is now {rank:?}.")).await;
+                    }
+                    FromServer::Save => connection.save_player_data(),
+                    FromServer::Kick => {
+                        tracing::info!("Connection {:#?} was kicked!", client_handle.id);
+                        break;
+                    }
+                    FromServer::Shutdown => {
+                        connection.send_message("The server is shutting down.").await;
+                        break;
+                    }
                 },
                 None => break,
             }
@@ -1009,10 +1017,7 @@ async fn client_loop(
     }
 
     // forcefully log out the player if they weren't logging out but force D/C'd
-    if connection.player_data.actor_id != 0
-        && !connection.gracefully_logged_out
-        && is_zone_connection
-    {
+    if connection.player_data.actor_id != 0 && !connection.gracefully_logged_out {
         tracing::info!(
             "Forcefully logging out connection {:#?}...",
             client_handle.id
@@ -1025,6 +1030,143 @@ async fn client_loop(
     }
 }
 
+/// Spawn a new chat client actor.
+pub fn spawn_chat_client(connection: ChatConnection) {
+    let (send, recv) = channel(64);
+
+    // the actor id is filled in once the client sets up the connection
+    let handle = ClientHandle {
+        id: connection.id,
+        ip: connection.ip,
+        channel: send,
+        actor_id: 0,
+    };
+
+    tokio::spawn(chat_client_loop(connection, recv, handle));
+}
+
+async fn chat_client_loop(
+    mut connection: ChatConnection,
+    mut internal_recv: Receiver<FromServer>,
+    client_handle: ClientHandle,
+) {
+    let mut buf = vec![0; RECEIVE_BUFFER_SIZE];
+    'client: loop {
+        tokio::select! {
+            biased; // client data should always be prioritized
+            n = connection.socket.read(&mut buf) => {
+                match n {
+                    Ok(n) => {
+                        // if the last response was over >5 seconds, the client is probably gone
+                        if n == 0 {
+                            let now = Instant::now();
+                            if now.duration_since(connection.last_keep_alive) > Duration::from_secs(5) {
+                                tracing::info!("Chat connection {:#?} was killed because of timeout", client_handle.id);
+                                break;
+                            }
+                        }
+
+                        if n > 0 {
+                            connection.last_keep_alive = Instant::now();
+
+                            let (segments, _) = connection.parse_packet(&buf[..n]);
+                            for segment in &segments {
+                                match &segment.data {
+                                    SegmentData::None() => {},
+                                    SegmentData::Setup { ticket } => {
+                                        // for some reason they send a string representation
+                                        let Ok(actor_id) = ticket.parse::<u32>() else {
+                                            tracing::warn!("Chat connection {:#?} sent an invalid ticket {ticket:?}, disconnecting!", client_handle.id);
+                                            break 'client;
+                                        };
+
+                                        connection.initialize(actor_id).await;
+
+                                        let mut client_handle = client_handle.clone();
+                                        client_handle.actor_id = actor_id;
+
+                                        connection
+                                            .handle
+                                            .send(ToServer::NewChatClient(client_handle))
+                                            .await;
+                                    }
+                                    SegmentData::Ipc { data } => {
+                                        match &data.data {
+                                            ClientChatIpcData::Unknown { .. } => {
+                                                tracing::warn!("Unknown chat packet {:?} recieved, this should be handled!", data.op_code);
+                                            }
+                                        }
+                                    }
+                                    SegmentData::KeepAliveRequest { id, timestamp } => {
+                                        send_keep_alive::<ServerChatIpcSegment>(
+                                            &mut connection.socket,
+                                            &mut connection.state,
+                                            ConnectionType::Chat,
+                                            *id,
+                                            *timestamp,
+                                        )
+                                        .await
+                                    }
+                                    SegmentData::KeepAliveResponse { .. } => {
+                                        tracing::info!("Got keep alive response from client... cool...");
+                                    }
+                                    _ => {
+                                        tracing::warn!("Chat connection {:#?} sent a response or unknown packet, ignoring it: {segment:#?}", client_handle.id);
+                                    }
+                                }
+                            }
+                        }
+                    },
+                    Err(_) => {
+                        tracing::info!("Chat connection {:#?} was killed because of a network error!", client_handle.id);
+                        break;
+                    },
+                }
+            }
+            msg = internal_recv.recv() => match msg {
+                // everything is for the zone connection for now
+                Some(_) => {}
+                None => break,
+            }
+        }
+    }
+
+    connection
+        .handle
+        .send(ToServer::Disconnected(connection.id))
+        .await;
+}
+
+/// Peeks at the header of the first packet, to figure out what kind of connection this is without consuming anything.
+/// Returns None if the connection closed, or the header didn't arrive within `FIRST_PACKET_TIMEOUT`.
+async fn peek_connection_type(socket: &TcpStream) -> Option<ConnectionType> {
+    let header_size = std::mem::size_of::<PacketHeader>();
+    let mut buf = vec![0; header_size];
+
+    let peek_header = async {
+        loop {
+            let n = socket.peek(&mut buf).await.ok()?;
+            if n == 0 {
+                return None;
+            }
+
+            if n >= header_size {
+                return Some(());
+            }
+
+            // wait for the rest of the header to arrive
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    };
+
+    tokio::time::timeout(FIRST_PACKET_TIMEOUT, peek_header)
+        .await
+        .ok()??;
+
+    let header = PacketHeader::read_le(&mut std::io::Cursor::new(&buf)).ok()?;
+    Some(header.connection_type)
+}
+
 async fn handle_rcon(listener: &Option<TcpListener>) -> Option<(TcpStream, SocketAddr)> {
     match listener {
         Some(listener) => Some(listener.accept().await.ok()?),
@@ -1032,12 +1174,123 @@ async fn handle_rcon(listener: &Option<TcpListener>) -> Option<(TcpStream, Socke
     }
 }
 
+/// Waits until we're asked to stop, either by Ctrl+C or SIGTERM.
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{SignalKind, signal};
+
+        let mut terminate = signal(SignalKind::terminate()).unwrap();
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = terminate.recv() => {}
+        }
+    }
+
+    #[cfg(not(unix))]
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+/// Handles a single RCON client, until they disconnect.
+async fn rcon_session(mut socket: TcpStream, mut handle: ServerHandle) {
+    let mut authenticated = false;
+
+    loop {
+        // read from client
+        let mut resp_bytes = [0u8; rkon::MAX_PACKET_SIZE];
+        match socket.read(&mut resp_bytes).await {
+            Ok(0) | Err(_) => break,
+            Ok(_) => {}
+        }
+
+        let request = match rkon::Packet::decode(&resp_bytes) {
+            Ok(request) => request,
+            Err(err) => {
+                tracing::warn!("RCON client sent a malformed packet, closing the session: {err:?}");
+                break;
+            }
+        };
+
+        let response = match request.packet_type {
+            rkon::PacketType::Command => {
+                if !authenticated {
+                    continue;
+                }
+
+                let body = match RconCommand::parse(&request.body) {
+                    Ok(command) => {
+                        tracing::info!("Running RCON command {command:?}");
+
+                        let (send, recv) = oneshot::channel();
+                        handle.send(ToServer::Rcon(command, send)).await;
+                        recv.await.unwrap_or_default()
+                    }
+                    Err(message) => message,
+                };
+
+                rkon::Packet {
+                    request_id: request.request_id,
+                    packet_type: rkon::PacketType::Command,
+                    body,
+                }
+            }
+            rkon::PacketType::Login => {
+                let config = get_config();
+                authenticated = request.body == config.world.rcon_password;
+
+                rkon::Packet {
+                    request_id: if authenticated {
+                        request.request_id
+                    } else {
+                        -1
+                    },
+                    packet_type: rkon::PacketType::Command,
+                    body: String::default(),
+                }
+            }
+            _ => {
+                tracing::warn!("Ignoring unknown RCON packet");
+                continue;
+            }
+        };
+
+        if socket.write_all(&response.encode()).await.is_err() {
+            break;
+        }
+    }
+
+    tracing::info!("RCON client disconnected");
+}
+
+/// Handles a single connection from one of the other servers, until they disconnect.
+async fn internal_session(mut connection: InternalConnection) {
+    while let Some(segments) = connection.read_segments().await {
+        for segment in &segments {
+            let SegmentData::KawariIpc { data } = &segment.data else {
+                tracing::warn!("Ignoring non-custom IPC segment from {}", connection.ip);
+                continue;
+            };
+
+            if !handle_custom_ipc(&mut connection, data).await {
+                return;
+            }
+        }
+    }
+}
+
 #[tokio::main]
 async fn main() {
     tracing_subscriber::fmt::init();
 
     let config = get_config();
 
+    if let Err(reason) = check_internal_secret(&config.world.internal_secret) {
+        tracing::error!(
+            "Refusing to start, {reason}! Set world.internal_secret in the config, otherwise anyone who can reach the internal port can manage characters."
+        );
+        return;
+    }
+
     let addr = config.world.get_socketaddr();
 
     let listener = TcpListener::bind(addr).await.unwrap();
@@ -1052,6 +1305,10 @@ async fn main() {
         None
     };
 
+    let internal_listener = TcpListener::bind(config.world.get_internal_socketaddr())
+        .await
+        .unwrap();
+
     tracing::info!("Server started on {addr}");
 
     let database = Arc::new(WorldDatabase::new());
@@ -1065,7 +1322,10 @@ async fn main() {
         }
     }
 
-    let (handle, _) = spawn_main_loop();
+    let (mut handle, _) = spawn_main_loop(database.actor_ids.clone(), lua.clone());
+
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
 
     loop {
         tokio::select! {
@@ -1078,80 +1338,84 @@ async fn main() {
                     serverbound_oodle: OodleNetwork::new(),
                 };
 
-                spawn_client(ZoneConnection {
+                let handle = handle.clone();
+                let database = database.clone();
+                let lua = lua.clone();
+                let game_data = game_data.clone();
+
+                // the client opens separate zone and chat connections, which we can only tell apart by their first packet
+                tokio::spawn(async move {
+                    match peek_connection_type(&socket).await {
+                        Some(ConnectionType::Chat) => spawn_chat_client(ChatConnection {
+                            config: get_config().world,
+                            socket,
+                            state,
+                            actor_id: 0,
+                            content_id: 0,
+                            name: String::new(),
+                            ip,
+                            id,
+                            handle,
+                            database,
+                            last_keep_alive: Instant::now(),
+                        }),
+                        Some(_) => spawn_client(ZoneConnection {
+                            config: get_config().world,
+                            socket,
+                            state,
+                            player_data: PlayerData::default(),
+                            saved_player_data: PlayerData::default(),
+                            spawn_index: 0,
+                            zone: None,
+                            status_effects: StatusEffects::default(),
+                            event: None,
+                            actors: Vec::new(),
+                            ip,
+                            id,
+                            handle,
+                            database,
+                            lua,
+                            gamedata: game_data,
+                            exit_position: None,
+                            exit_rotation: None,
+                            last_keep_alive: Instant::now(),
+                            gracefully_logged_out: false,
+                            weather_id: 0,
+                        }),
+                        None => tracing::info!("Connection {id:#?} closed or timed out before sending anything"),
+                    }
+                });
+            }
+            Ok((socket, ip)) = internal_listener.accept() => {
+                tokio::spawn(internal_session(InternalConnection {
                     config: get_config().world,
                     socket,
-                    state,
-                    player_data: PlayerData::default(),
-                    spawn_index: 0,
-                    zone: None,
-                    status_effects: StatusEffects::default(),
-                    event: None,
-                    actors: Vec::new(),
+                    state: PacketState {
+                        client_key: None,
+                        clientbound_oodle: OodleNetwork::new(),
+                        serverbound_oodle: OodleNetwork::new(),
+                    },
                     ip,
-                    id,
-                    handle: handle.clone(),
+                    authenticated: false,
                     database: database.clone(),
-                    lua: lua.clone(),
                     gamedata: game_data.clone(),
-                    exit_position: None,
-                    exit_rotation: None,
-                    last_keep_alive: Instant::now(),
-                    gracefully_logged_out: false,
-                    weather_id: 0,
-                });
+                }));
             }
-            Some((mut socket, _)) = handle_rcon(&rcon_listener) => {
-                let mut authenticated = false;
-
-                loop {
-                    // read from client
-                    let mut resp_bytes = [0u8; rkon::MAX_PACKET_SIZE];
-                    let n = socket.read(&mut resp_bytes).await.unwrap();
-                    if n > 0 {
-                        let request = rkon::Packet::decode(&resp_bytes).unwrap();
-
-                        match request.packet_type {
-                            rkon::PacketType::Command => {
-                                if authenticated {
-                                    let response = rkon::Packet {
-                                        request_id: request.request_id,
-                                        packet_type: rkon::PacketType::Command,
-                                        body: "hello world!".to_string()
-                                    };
-                                    let encoded = response.encode();
-                                    socket.write_all(&encoded).await.unwrap();
-                                }
-                            },
-                            rkon::PacketType::Login => {
-                                let config = get_config();
-                                if request.body == config.world.rcon_password {
-                                    authenticated = true;
-
-                                    let response = rkon::Packet {
-                                        request_id: request.request_id,
-                                        packet_type: rkon::PacketType::Command,
-                                        body: String::default()
-                                    };
-                                    let encoded = response.encode();
-                                    socket.write_all(&encoded).await.unwrap();
-                                } else {
-                                    authenticated = false;
-
-                                    let response = rkon::Packet {
-                                        request_id: -1,
-                                        packet_type: rkon::PacketType::Command,
-                                        body: String::default()
-                                    };
-                                    let encoded = response.encode();
-                                    socket.write_all(&encoded).await.unwrap();
-                                }
-                            },
-                            _ => tracing::warn!("Ignoring unknown RCON packet")
-                        }
-                    }
-                }
+            Some((socket, ip)) = handle_rcon(&rcon_listener) => {
+                tracing::info!("RCON client connected from {ip}");
+
+                tokio::spawn(rcon_session(socket, handle.clone()));
             }
+            _ = &mut shutdown => break,
         };
     }
+
+    // make sure everyone is saved before we exit
+    tracing::info!("Shutting down...");
+
+    let (send, recv) = oneshot::channel();
+    handle.send(ToServer::Shutdown(send)).await;
+    if tokio::time::timeout(SHUTDOWN_TIMEOUT, recv).await.is_err() {
+        tracing::warn!("Timed out waiting for everyone to disconnect, some progress may be lost!");
+    }
 }
@@ -1,6 +1,8 @@
 use icarus::Action::ActionSheet;
 use icarus::Aetheryte::AetheryteSheet;
+use icarus::BNpcBase::BNpcBaseSheet;
 use icarus::ClassJob::ClassJobSheet;
+use icarus::ENpcBase::ENpcBaseSheet;
 use icarus::EquipSlotCategory::EquipSlotCategorySheet;
 use icarus::GilShopItem::GilShopItemSheet;
 use icarus::PlaceName::PlaceNameSheet;
@@ -328,6 +330,22 @@ impl GameData {
         row.Cast100ms().into_u16().copied()
     }
 
+    /// Returns the ModelChara of a battle NPC, which has to match its BNpcBase for it to spawn.
+    pub fn get_bnpc_model_chara(&mut self, bnpc_base_id: u32) -> Option<u16> {
+        let sheet = BNpcBaseSheet::read_from(&mut self.game_data, Language::None)?;
+        let row = sheet.get_row(bnpc_base_id)?;
+
+        row.ModelChara().into_u16().copied()
+    }
+
+    /// Returns the ModelChara of an event NPC.
+    pub fn get_enpc_model_chara(&mut self, enpc_base_id: u32) -> Option<u16> {
+        let sheet = ENpcBaseSheet::read_from(&mut self.game_data, Language::None)?;
+        let row = sheet.get_row(enpc_base_id)?;
+
+        row.ModelChara().into_u16().copied()
+    }
+
     /// Calculates the current weather at the current time
     // TODO: instead allow targetting a specific time to calculate forcecasts
     pub fn get_weather_rate(&mut self, weather_rate_id: u32) -> Option<i32> {
@@ -387,6 +405,14 @@ impl GameData {
         self.get_weather_rate(*weather_rate_id as u32)
     }
 
+    /// Gets the TerritoryIntendedUse for the given zone id
+    pub fn get_territory_intended_use(&mut self, zone_id: u32) -> Option<u8> {
+        let sheet = TerritoryTypeSheet::read_from(&mut self.game_data, Language::None)?;
+        let row = sheet.get_row(zone_id)?;
+
+        row.TerritoryIntendedUse().into_u8().copied()
+    }
+
     /// Gets the array index used in EXP & levels.
     pub fn get_exp_array_index(&self, classjob_id: u16) -> Option<i8> {
         self.classjob_exp_indexes.get(classjob_id as usize).copied()
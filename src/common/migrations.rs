@@ -0,0 +1,100 @@
+use rusqlite::{Connection, OptionalExtension};
+
+/// Brings a database's schema up to date, by running every migration it hasn't seen yet.
+///
+/// Each migration is a batch of SQL, and they run in order. The number of migrations that have been applied is stored in the `schema_version` table.
+/// Once a migration has been released it must never be changed, instead add a new one after it.
+pub fn run_migrations(connection: &mut Connection, migrations: &[&str]) {
+    connection
+        .execute(
+            "CREATE TABLE IF NOT EXISTS schema_version (version INTEGER NOT NULL);",
+            (),
+        )
+        .unwrap();
+
+    let version: u32 = connection
+        .query_row("SELECT version FROM schema_version", (), |row| row.get(0))
+        .optional()
+        .unwrap()
+        .unwrap_or(0);
+    let version = version as usize;
+
+    if version > migrations.len() {
+        panic!(
+            "Database is at schema version {version}, but we only know up to {}. Was it created by a newer version of Kawari?",
+            migrations.len()
+        );
+    }
+
+    for (i, migration) in migrations.iter().enumerate().skip(version) {
+        let new_version = i as u32 + 1;
+
+        tracing::info!("Migrating database to schema version {new_version}...");
+
+        // if anything goes wrong, we don't want to be left with a half-migrated database
+        let transaction = connection.transaction().unwrap();
+        transaction.execute_batch(migration).unwrap();
+        transaction
+            .execute("DELETE FROM schema_version", ())
+            .unwrap();
+        transaction
+            .execute("INSERT INTO schema_version VALUES (?1)", (new_version,))
+            .unwrap();
+        transaction.commit().unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MIGRATIONS: &[&str] = &[
+        "CREATE TABLE IF NOT EXISTS test (id INTEGER PRIMARY KEY);",
+        "ALTER TABLE test ADD COLUMN name TEXT;",
+    ];
+
+    fn schema_version(connection: &Connection) -> u32 {
+        connection
+            .query_row("SELECT version FROM schema_version", (), |row| row.get(0))
+            .unwrap()
+    }
+
+    #[test]
+    fn migrate_new_database() {
+        let mut connection = Connection::open_in_memory().unwrap();
+        run_migrations(&mut connection, MIGRATIONS);
+
+        assert_eq!(schema_version(&connection), 2);
+        connection
+            .execute("INSERT INTO test VALUES (1, 'test')", ())
+            .unwrap();
+    }
+
+    #[test]
+    fn migrate_existing_database() {
+        let mut connection = Connection::open_in_memory().unwrap();
+        run_migrations(&mut connection, &MIGRATIONS[..1]);
+        connection
+            .execute("INSERT INTO test VALUES (1)", ())
+            .unwrap();
+
+        assert_eq!(schema_version(&connection), 1);
+
+        // running them again should only apply the new one, and keep existing data
+        run_migrations(&mut connection, MIGRATIONS);
+
+        assert_eq!(schema_version(&connection), 2);
+        let id: u32 = connection
+            .query_row("SELECT id FROM test", (), |row| row.get(0))
+            .unwrap();
+        assert_eq!(id, 1);
+    }
+
+    #[test]
+    #[should_panic]
+    fn migrate_newer_database() {
+        let mut connection = Connection::open_in_memory().unwrap();
+        run_migrations(&mut connection, MIGRATIONS);
+        run_migrations(&mut connection, &MIGRATIONS[..1]);
+    }
+}
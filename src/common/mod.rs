@@ -16,6 +16,11 @@ pub use gamedata::{ItemInfo, ItemInfoQuery, TerritoryNameKind};
 
 pub mod workdefinitions;
 
+#[cfg(not(target_family = "wasm"))]
+mod migrations;
+#[cfg(not(target_family = "wasm"))]
+pub use migrations::run_migrations;
+
 #[binrw]
 #[brw(little)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -52,6 +57,28 @@ pub const INVALID_OBJECT_ID: ObjectId = ObjectId(0xE0000000);
 /// Maxmimum length of a character's name.
 pub const CHAR_NAME_MAX_LENGTH: usize = 32;
 
+/// Checks if a character name follows the same rules the client enforces: a forename and a surname of 2-15 letters each,
+/// at most 20 letters together, each starting with a letter. Apostrophes and hyphens are allowed, but not twice in a row.
+pub fn is_valid_character_name(name: &str) -> bool {
+    let Some((forename, surname)) = name.split_once(' ') else {
+        return false;
+    };
+
+    let is_valid_part = |part: &str| {
+        (2..=15).contains(&part.len())
+            && part.starts_with(|c: char| c.is_ascii_alphabetic())
+            && part
+                .chars()
+                .all(|c| c.is_ascii_alphabetic() || c == '\'' || c == '-')
+            && !part
+                .as_bytes()
+                .windows(2)
+                .any(|pair| !pair[0].is_ascii_alphabetic() && !pair[1].is_ascii_alphabetic())
+    };
+
+    is_valid_part(forename) && is_valid_part(surname) && forename.len() + surname.len() <= 20
+}
+
 pub(crate) fn read_bool_from<T: std::convert::From<u8> + std::cmp::PartialEq>(x: T) -> bool {
     x == T::from(1u8)
 }
@@ -147,6 +174,19 @@ pub fn determine_initial_starting_zone(citystate_id: u8) -> u16 {
     }
 }
 
+/// Gets the initial home point aetheryte for a given city-state id
+pub fn determine_initial_homepoint(citystate_id: u8) -> u32 {
+    match citystate_id {
+        // Limsa Lominsa Lower Decks
+        1 => 8,
+        // New Gridania
+        2 => 2,
+        // Ul'dah - Steps of Nald
+        3 => 9,
+        _ => panic!("This is not a valid city-state id!"),
+    }
+}
+
 pub fn value_to_flag_byte_index_value(in_value: u32) -> (u8, u16) {
     let bit_index = in_value % 8;
     (1 << bit_index, (in_value / 8) as u16)
@@ -199,6 +239,24 @@ mod tests {
         );
     }
 
+    #[test]
+    fn character_names() {
+        assert!(is_valid_character_name("Test Name"));
+        assert!(is_valid_character_name("Y'shtola Rhul"));
+        assert!(is_valid_character_name("Lyse Hext-Ra"));
+
+        assert!(!is_valid_character_name(""));
+        assert!(!is_valid_character_name("Mononym"));
+        assert!(!is_valid_character_name("Too Many Names"));
+        assert!(!is_valid_character_name("A Name"));
+        assert!(!is_valid_character_name("Averyveryverylong Name"));
+        assert!(!is_valid_character_name("Fifteenletters Fifteenletters"));
+        assert!(!is_valid_character_name("'Quote Name"));
+        assert!(!is_valid_character_name("Double--Hyphen Name"));
+        assert!(!is_valid_character_name("Num83rs Name"));
+        assert!(!is_valid_character_name("Test  Name"));
+    }
+
     #[test]
     fn quantized_rotations() {
         assert_eq!(read_quantized_rotation(0), -std::f32::consts::PI);
@@ -2,9 +2,20 @@ use binrw::binrw;
 use serde::{Deserialize, Serialize};
 
 #[binrw]
-#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
 pub struct Position {
     pub x: f32,
     pub y: f32,
     pub z: f32,
 }
+
+impl Position {
+    /// Returns the distance between this position and `other`, in yalms.
+    pub fn distance(&self, other: &Position) -> f32 {
+        let x = self.x - other.x;
+        let y = self.y - other.y;
+        let z = self.z - other.z;
+
+        (x * x + y * y + z * z).sqrt()
+    }
+}
@@ -5,6 +5,23 @@ use std::{
 
 use serde::{Deserialize, Serialize};
 
+use crate::ipc::kawari::INTERNAL_SECRET_MAX_LENGTH;
+
+/// Checks if a shared secret for the internal port can be used, returning why not otherwise.
+pub fn check_internal_secret(secret: &str) -> Result<(), String> {
+    if secret.is_empty() {
+        return Err("no internal secret is set".to_string());
+    }
+
+    if secret.len() > INTERNAL_SECRET_MAX_LENGTH {
+        return Err(format!(
+            "the internal secret is longer than {INTERNAL_SECRET_MAX_LENGTH} bytes"
+        ));
+    }
+
+    Ok(())
+}
+
 /// Configuration for the admin server.
 #[derive(Serialize, Deserialize)]
 pub struct AdminConfig {
@@ -94,6 +111,12 @@ pub struct LoginConfig {
     pub listen_address: String,
     /// Public-facing domain of the server.
     pub server_name: String,
+    /// How long (in seconds) someone stays logged in before they have to log in again.
+    #[serde(default = "LoginConfig::default_session_lifetime")]
+    pub session_lifetime: u32,
+    /// How many sessions a user can have at once, logging in again ends their oldest one. Set to 0 for no limit.
+    #[serde(default = "LoginConfig::default_max_sessions")]
+    pub max_sessions: u32,
 }
 
 impl Default for LoginConfig {
@@ -102,10 +125,22 @@ impl Default for LoginConfig {
             port: 6700,
             listen_address: "0.0.0.0".to_string(),
             server_name: "ffxiv-login.square.localhost".to_string(),
+            session_lifetime: Self::default_session_lifetime(),
+            max_sessions: Self::default_max_sessions(),
         }
     }
 }
 
+impl LoginConfig {
+    fn default_session_lifetime() -> u32 {
+        60 * 60 * 24 // one day
+    }
+
+    fn default_max_sessions() -> u32 {
+        5
+    }
+}
+
 impl LoginConfig {
     /// Returns the configured IP address & port as a `SocketAddr`.
     pub fn get_socketaddr(&self) -> SocketAddr {
@@ -216,6 +251,21 @@ pub struct WorldConfig {
     /// Enable packet compression for packets from the server. It's recommended to keep this on.
     #[serde(default = "WorldConfig::default_packet_compression")]
     pub enable_packet_compression: bool,
+    /// How often (in seconds) online players are written back to the database. Set to 0 to disable autosaving.
+    #[serde(default = "WorldConfig::default_autosave_interval")]
+    pub autosave_interval: u64,
+    /// How long (in seconds) a battle NPC takes to come back after its corpse despawns.
+    #[serde(default = "WorldConfig::default_respawn_time")]
+    pub respawn_time: u64,
+    /// Port the other servers use to talk to this world server.
+    #[serde(default = "WorldConfig::default_internal_port")]
+    pub internal_port: u16,
+    /// Address to listen on for the other servers. Only local connections are accepted by default.
+    #[serde(default = "WorldConfig::default_internal_listen_address")]
+    pub internal_listen_address: String,
+    /// Secret the other servers have to send before they can make requests, up to `INTERNAL_SECRET_MAX_LENGTH` bytes. The server refuses to start without one.
+    #[serde(default)]
+    pub internal_secret: String,
 }
 
 impl Default for WorldConfig {
@@ -230,6 +280,11 @@ impl Default for WorldConfig {
             rcon_password: Self::default_rcon_password(),
             enable_packet_obsfucation: Self::default_packet_obsfucation(),
             enable_packet_compression: Self::default_packet_compression(),
+            autosave_interval: Self::default_autosave_interval(),
+            respawn_time: Self::default_respawn_time(),
+            internal_port: Self::default_internal_port(),
+            internal_listen_address: Self::default_internal_listen_address(),
+            internal_secret: String::default(),
         }
     }
 }
@@ -270,6 +325,22 @@ impl WorldConfig {
     fn default_packet_compression() -> bool {
         true
     }
+
+    fn default_autosave_interval() -> u64 {
+        300
+    }
+
+    fn default_respawn_time() -> u64 {
+        60
+    }
+
+    fn default_internal_port() -> u16 {
+        7110
+    }
+
+    fn default_internal_listen_address() -> String {
+        "127.0.0.1".to_string()
+    }
 }
 
 impl WorldConfig {
@@ -295,6 +366,64 @@ impl WorldConfig {
             self.rcon_port,
         ))
     }
+
+    /// Returns the configured IP address & port as a `SocketAddr` for the other servers.
+    pub fn get_internal_socketaddr(&self) -> SocketAddr {
+        SocketAddr::from((
+            IpAddr::from_str(&self.internal_listen_address)
+                .expect("Invalid IP address format in config!"),
+            self.internal_port,
+        ))
+    }
+}
+
+/// A world listed in the lobby. Each one is served by its own world server.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct WorldListingConfig {
+    /// See the World Excel sheet.
+    pub id: u16,
+    /// Name shown in the lobby. If left blank, the name from the World Excel sheet is used.
+    #[serde(default)]
+    pub name: String,
+    /// Address of the world server.
+    pub server_name: String,
+    /// Port of the world server.
+    pub port: u16,
+    /// The data center this world belongs to.
+    #[serde(default)]
+    pub data_center: String,
+    /// Address the other servers reach the world server's internal port on.
+    #[serde(default = "WorldListingConfig::default_internal_server_name")]
+    pub internal_server_name: String,
+    /// Port the world server listens on for the other servers.
+    #[serde(default = "WorldConfig::default_internal_port")]
+    pub internal_port: u16,
+    /// Must match `internal_secret` in the world server's config.
+    #[serde(default)]
+    pub internal_secret: String,
+}
+
+impl WorldListingConfig {
+    fn default_internal_server_name() -> String {
+        "127.0.0.1".to_string()
+    }
+
+    /// Returns the world server's address & port as a `SocketAddr`.
+    pub fn get_socketaddr(&self) -> SocketAddr {
+        SocketAddr::from((
+            IpAddr::from_str(&self.server_name).expect("Invalid IP address format in config!"),
+            self.port,
+        ))
+    }
+
+    /// Returns the world server's address & internal port as a `SocketAddr`.
+    pub fn get_internal_socketaddr(&self) -> SocketAddr {
+        SocketAddr::from((
+            IpAddr::from_str(&self.internal_server_name)
+                .expect("Invalid IP address format in config!"),
+            self.internal_port,
+        ))
+    }
 }
 
 /// Configuration for the launcher server.
@@ -388,6 +517,10 @@ pub struct Config {
     #[serde(default)]
     pub save_data_bank: SaveDataBankConfig,
 
+    /// Every world shown in the lobby. If this is empty, only the world from `world` is listed.
+    #[serde(default)]
+    pub worlds: Vec<WorldListingConfig>,
+
     /// Enable various packet debug functions. This will clutter your working directory!
     #[serde(default)]
     pub packet_debugging: bool,
@@ -407,6 +540,7 @@ impl Default for Config {
             world: WorldConfig::default(),
             launcher: LauncherConfig::default(),
             save_data_bank: SaveDataBankConfig::default(),
+            worlds: Vec::new(),
             packet_debugging: false,
         }
     }
@@ -416,6 +550,32 @@ impl Config {
     pub fn supports_platform(&self, platform: &String) -> bool {
         self.supported_platforms.contains(platform)
     }
+
+    /// Returns every world that should be listed in the lobby.
+    pub fn get_worlds(&self) -> Vec<WorldListingConfig> {
+        if !self.worlds.is_empty() {
+            return self.worlds.clone();
+        }
+
+        vec![WorldListingConfig {
+            id: self.world.world_id,
+            name: String::new(),
+            server_name: self.world.server_name.clone(),
+            port: self.world.port,
+            data_center: String::new(),
+            // it's the same config as the world server's, so it's running on this machine
+            internal_server_name: WorldListingConfig::default_internal_server_name(),
+            internal_port: self.world.internal_port,
+            internal_secret: self.world.internal_secret.clone(),
+        }]
+    }
+
+    /// Finds a world listed in the lobby by its id.
+    pub fn find_world(&self, world_id: u16) -> Option<WorldListingConfig> {
+        self.get_worlds()
+            .into_iter()
+            .find(|world| world.id == world_id)
+    }
 }
 
 fn default_supported_platforms() -> Vec<String> {
@@ -2,7 +2,7 @@ use serde::{Deserialize, Serialize};
 
 use super::{Item, Storage};
 
-#[derive(Clone, Copy, Deserialize, Serialize, Debug)]
+#[derive(Clone, Copy, PartialEq, Deserialize, Serialize, Debug)]
 pub struct CurrencyStorage {
     pub gil: Item,
 }
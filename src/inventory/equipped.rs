@@ -2,7 +2,7 @@ use serde::{Deserialize, Serialize};
 
 use super::{Item, Storage};
 
-#[derive(Default, Clone, Copy, Deserialize, Serialize, Debug)]
+#[derive(Default, Clone, Copy, PartialEq, Deserialize, Serialize, Debug)]
 pub struct EquippedStorage {
     pub main_hand: Item,
     pub off_hand: Item,
@@ -48,6 +48,7 @@ impl Storage for EquippedStorage {
             2 => &mut self.head,
             3 => &mut self.body,
             4 => &mut self.hands,
+            5 => &mut self.belt,
             6 => &mut self.legs,
             7 => &mut self.feet,
             8 => &mut self.ears,
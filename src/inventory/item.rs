@@ -1,7 +1,7 @@
 use serde::{Deserialize, Serialize};
 
 /// Represents an item, or if the quanity is zero an empty slot.
-#[derive(Default, Copy, Clone, Serialize, Deserialize, Debug)]
+#[derive(Default, Copy, Clone, PartialEq, Serialize, Deserialize, Debug)]
 pub struct Item {
     pub quantity: u32,
     pub id: u32,
@@ -24,7 +24,7 @@ pub use currency::CurrencyStorage;
 const MAX_NORMAL_STORAGE: usize = 35;
 const MAX_LARGE_STORAGE: usize = 50;
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 pub struct Inventory {
     pub equipped: EquippedStorage,
     pub pages: [GenericStorage<MAX_NORMAL_STORAGE>; 4],
@@ -210,7 +210,7 @@ impl Inventory {
         }
     }
 
-    fn get_container_mut(&mut self, container_type: &ContainerType) -> &mut dyn Storage {
+    pub fn get_container_mut(&mut self, container_type: &ContainerType) -> &mut dyn Storage {
         match container_type {
             ContainerType::Inventory0 => &mut self.pages[0],
             ContainerType::Inventory1 => &mut self.pages[1],
@@ -233,7 +233,7 @@ impl Inventory {
         }
     }
 
-    fn get_container(&self, container_type: &ContainerType) -> &dyn Storage {
+    pub fn get_container(&self, container_type: &ContainerType) -> &dyn Storage {
         match container_type {
             ContainerType::Inventory0 => &self.pages[0],
             ContainerType::Inventory1 => &self.pages[1],
@@ -32,6 +32,34 @@ pub enum ContainerType {
     ArmoryWeapon = 3500,
 }
 
+impl TryFrom<u16> for ContainerType {
+    type Error = ();
+
+    fn try_from(value: u16) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Self::Inventory0),
+            1 => Ok(Self::Inventory1),
+            2 => Ok(Self::Inventory2),
+            3 => Ok(Self::Inventory3),
+            1000 => Ok(Self::Equipped),
+            2000 => Ok(Self::Currency),
+            3200 => Ok(Self::ArmoryOffWeapon),
+            3201 => Ok(Self::ArmoryHead),
+            3202 => Ok(Self::ArmoryBody),
+            3203 => Ok(Self::ArmoryHand),
+            3205 => Ok(Self::ArmoryLeg),
+            3206 => Ok(Self::ArmoryFoot),
+            3207 => Ok(Self::ArmoryEarring),
+            3208 => Ok(Self::ArmoryNeck),
+            3209 => Ok(Self::ArmoryWrist),
+            3300 => Ok(Self::ArmoryRing),
+            3400 => Ok(Self::ArmorySoulCrystal),
+            3500 => Ok(Self::ArmoryWeapon),
+            _ => Err(()),
+        }
+    }
+}
+
 /// Represents a generic item storage.
 pub trait Storage: Sync {
     fn max_slots(&self) -> u32;
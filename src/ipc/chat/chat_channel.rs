@@ -0,0 +1,60 @@
+/// The channel a chat message was sent in. See the LogKind Excel sheet.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[repr(u16)]
+pub enum ChatChannel {
+    Say = 10,
+    Shout = 11,
+    Tell = 12,
+    Party = 14,
+    Alliance = 15,
+    Linkshell1 = 16,
+    Linkshell2 = 17,
+    Linkshell3 = 18,
+    Linkshell4 = 19,
+    Linkshell5 = 20,
+    Linkshell6 = 21,
+    Linkshell7 = 22,
+    Linkshell8 = 23,
+    FreeCompany = 24,
+    NoviceNetwork = 27,
+    CustomEmote = 28,
+    StandardEmote = 29,
+    Yell = 30,
+}
+
+impl ChatChannel {
+    /// Converts the raw channel id the client sends, or returns None if we don't know it.
+    pub fn from_id(id: u16) -> Option<Self> {
+        Some(match id {
+            10 => Self::Say,
+            11 => Self::Shout,
+            12 => Self::Tell,
+            14 => Self::Party,
+            15 => Self::Alliance,
+            16 => Self::Linkshell1,
+            17 => Self::Linkshell2,
+            18 => Self::Linkshell3,
+            19 => Self::Linkshell4,
+            20 => Self::Linkshell5,
+            21 => Self::Linkshell6,
+            22 => Self::Linkshell7,
+            23 => Self::Linkshell8,
+            24 => Self::FreeCompany,
+            27 => Self::NoviceNetwork,
+            28 => Self::CustomEmote,
+            29 => Self::StandardEmote,
+            30 => Self::Yell,
+            _ => return None,
+        })
+    }
+
+    /// Returns which linkshell (from 0 to 7) this channel is for, or None if it isn't a linkshell channel.
+    pub fn linkshell_index(&self) -> Option<u8> {
+        let id = *self as u16;
+        if (Self::Linkshell1 as u16..=Self::Linkshell8 as u16).contains(&id) {
+            Some((id - Self::Linkshell1 as u16) as u8)
+        } else {
+            None
+        }
+    }
+}
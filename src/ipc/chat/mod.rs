@@ -1,7 +1,10 @@
 use binrw::binrw;
 
+mod chat_channel;
+pub use chat_channel::ChatChannel;
+
 use crate::{
-    opcodes::ServerChatIpcType,
+    opcodes::{ClientChatIpcType, ServerChatIpcType},
     packet::{IpcSegment, ReadWriteIpcSegment},
 };
 
@@ -47,3 +50,70 @@ pub enum ServerChatIpcData {
         unk: Vec<u8>,
     },
 }
+
+pub type ClientChatIpcSegment = IpcSegment<ClientChatIpcType, ClientChatIpcData>;
+
+impl ReadWriteIpcSegment for ClientChatIpcSegment {
+    fn calc_size(&self) -> u32 {
+        // 16 is the size of the IPC header
+        16 + self.op_code.calc_size()
+    }
+
+    fn get_name(&self) -> &'static str {
+        self.op_code.get_name()
+    }
+}
+
+#[binrw]
+#[br(import(_magic: &ClientChatIpcType, size: &u32))]
+#[derive(Debug, Clone)]
+pub enum ClientChatIpcData {
+    Unknown {
+        #[br(count = size - 32)]
+        unk: Vec<u8>,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use binrw::BinWrite;
+
+    use super::*;
+
+    /// Ensure that the IPC data size as reported matches up with what we write
+    #[test]
+    fn server_chat_ipc_sizes() {
+        let ipc_types = [(
+            ServerChatIpcType::LoginReply,
+            ServerChatIpcData::LoginReply {
+                timestamp: 0,
+                sid: 0,
+            },
+        )];
+
+        for (opcode, data) in &ipc_types {
+            let mut cursor = Cursor::new(Vec::new());
+
+            let ipc_segment = ServerChatIpcSegment {
+                unk1: 0,
+                unk2: 0,
+                op_code: opcode.clone(), // doesn't matter for this test
+                option: 0,
+                timestamp: 0,
+                data: data.clone(),
+            };
+            ipc_segment.write_le(&mut cursor).unwrap();
+
+            let buffer = cursor.into_inner();
+
+            assert_eq!(
+                buffer.len(),
+                ipc_segment.calc_size() as usize,
+                "{:#?} did not match size!",
+                opcode
+            );
+        }
+    }
+}
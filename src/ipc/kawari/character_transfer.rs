@@ -0,0 +1,125 @@
+use binrw::binrw;
+
+use crate::common::{CHAR_NAME_MAX_LENGTH, Position, read_string, write_string};
+
+/// A single non-empty inventory slot.
+#[binrw]
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TransferredItem {
+    pub container: u16,
+    pub slot: u16,
+    pub id: u32,
+    pub quantity: u32,
+    pub condition: u16,
+    pub glamour_catalog_id: u32,
+}
+
+/// Everything stored about a character, used to move them from one world server to another.
+#[binrw]
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CharacterTransfer {
+    #[bw(pad_size_to = CHAR_NAME_MAX_LENGTH)]
+    #[br(count = CHAR_NAME_MAX_LENGTH)]
+    #[br(map = read_string)]
+    #[bw(map = write_string)]
+    pub name: String,
+    #[bw(pad_size_to = 1024)]
+    #[br(count = 1024)]
+    #[br(map = read_string)]
+    #[bw(map = write_string)]
+    pub chara_make_json: String,
+    pub city_state: u8,
+    pub homepoint: u32,
+    pub zone_id: u16,
+    pub position: Position,
+    pub rotation: f32,
+    pub remake_mode: i32,
+    pub gm_rank: u8,
+    pub classjob_id: u8,
+    pub classjob_levels: [i32; 32],
+    pub classjob_exp: [u32; 32],
+
+    #[bw(calc = items.len() as u16)]
+    num_items: u16,
+    #[br(count = num_items)]
+    pub items: Vec<TransferredItem>,
+
+    #[bw(calc = unlocks.len() as u16)]
+    unlocks_size: u16,
+    #[br(count = unlocks_size)]
+    pub unlocks: Vec<u8>,
+
+    #[bw(calc = aetherytes.len() as u16)]
+    aetherytes_size: u16,
+    #[br(count = aetherytes_size)]
+    pub aetherytes: Vec<u8>,
+
+    #[bw(calc = completed_quests.len() as u16)]
+    completed_quests_size: u16,
+    #[br(count = completed_quests_size)]
+    pub completed_quests: Vec<u8>,
+}
+
+impl CharacterTransfer {
+    /// How many bytes this takes up when written.
+    pub fn calc_size(&self) -> u32 {
+        const ITEM_SIZE: usize = 20;
+
+        (CHAR_NAME_MAX_LENGTH
+            + 1024
+            + 1
+            + 4
+            + 2
+            + 12
+            + 4
+            + 4
+            + 1
+            + 1
+            + 32 * 4
+            + 32 * 4
+            + 2
+            + self.items.len() * ITEM_SIZE
+            + 2
+            + self.unlocks.len()
+            + 2
+            + self.aetherytes.len()
+            + 2
+            + self.completed_quests.len()) as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use binrw::{BinRead, BinWrite};
+
+    use super::*;
+
+    #[test]
+    fn read_write_character_transfer() {
+        let character = CharacterTransfer {
+            name: "Test Character".to_string(),
+            chara_make_json: "{}".to_string(),
+            zone_id: 132,
+            homepoint: 2,
+            items: vec![TransferredItem {
+                container: 2000,
+                slot: 0,
+                id: 1,
+                quantity: 100,
+                ..Default::default()
+            }],
+            unlocks: vec![1, 2, 3],
+            completed_quests: vec![4],
+            ..Default::default()
+        };
+
+        let mut cursor = Cursor::new(Vec::new());
+        character.write_le(&mut cursor).unwrap();
+        assert_eq!(cursor.get_ref().len(), character.calc_size() as usize);
+
+        cursor.set_position(0);
+        assert_eq!(CharacterTransfer::read_le(&mut cursor).unwrap(), character);
+    }
+}
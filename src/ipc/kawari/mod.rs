@@ -1,13 +1,33 @@
 use binrw::binrw;
 
+mod character_transfer;
+pub use character_transfer::{CharacterTransfer, TransferredItem};
+
 use crate::{
     common::{CHAR_NAME_MAX_LENGTH, read_bool_from, read_string, write_bool_as, write_string},
     ipc::lobby::CharacterDetails,
     packet::{IpcSegment, ReadWriteIpcSegment},
 };
 
+/// The longest shared secret the servers can authenticate with.
+pub const INTERNAL_SECRET_MAX_LENGTH: usize = 64;
+
+/// Custom IPC has no use for the timestamp header field, so it carries a request id there instead. Responses copy it from
+/// their request, which is how they're matched up. See `request_id`.
 pub type CustomIpcSegment = IpcSegment<CustomIpcType, CustomIpcData>;
 
+impl CustomIpcSegment {
+    /// The id of the request this segment is, or is responding to. It's stored in the timestamp field.
+    pub fn request_id(&self) -> u32 {
+        self.timestamp
+    }
+
+    /// Sets the id of the request this segment is, or is responding to.
+    pub fn set_request_id(&mut self, request_id: u32) {
+        self.timestamp = request_id;
+    }
+}
+
 impl ReadWriteIpcSegment for CustomIpcSegment {
     fn calc_size(&self) -> u32 {
         // 16 is the size of the IPC header
@@ -25,6 +45,25 @@ impl ReadWriteIpcSegment for CustomIpcSegment {
             CustomIpcType::ImportCharacter => 132,
             CustomIpcType::RemakeCharacter => 1024 + 8,
             CustomIpcType::CharacterRemade => 8,
+            CustomIpcType::DeleteServiceAccount => 4,
+            CustomIpcType::ServiceAccountDeleted => 4,
+            CustomIpcType::RenameCharacter => 8 + CHAR_NAME_MAX_LENGTH as u32,
+            CustomIpcType::CharacterRenamed => 1,
+            CustomIpcType::Authenticate => INTERNAL_SECRET_MAX_LENGTH as u32,
+            CustomIpcType::Authenticated => 1,
+            CustomIpcType::CharacterImported => 4,
+            CustomIpcType::ExportCharacter => 8,
+            CustomIpcType::CharacterExported => match &self.data {
+                CustomIpcData::CharacterExported { character, .. } => 1 + character.calc_size(),
+                _ => 0,
+            },
+            CustomIpcType::ImportTransferredCharacter => match &self.data {
+                CustomIpcData::ImportTransferredCharacter { character, .. } => {
+                    4 + character.calc_size()
+                }
+                _ => 0,
+            },
+            CustomIpcType::TransferredCharacterImported => 1 + 8 + 4,
         }
     }
 
@@ -77,6 +116,28 @@ pub enum CustomIpcType {
     RemakeCharacter = 0x12,
     // Character has been remade
     CharacterRemade = 0x13,
+    /// Request that every character belonging to a service account be deleted
+    DeleteServiceAccount = 0x14,
+    /// Response to DeleteServiceAccount
+    ServiceAccountDeleted = 0x15,
+    /// Request that a character be renamed
+    RenameCharacter = 0x16,
+    /// Response to RenameCharacter
+    CharacterRenamed = 0x17,
+    /// Has to be sent before any other request, with the shared secret from the config
+    Authenticate = 0x18,
+    /// Response to Authenticate
+    Authenticated = 0x19,
+    /// Response to ImportCharacter
+    CharacterImported = 0x1A,
+    /// Request everything about a character, so they can be moved to another world
+    ExportCharacter = 0x1B,
+    /// Response to ExportCharacter
+    CharacterExported = 0x1C,
+    /// Add a character that was exported from another world
+    ImportTransferredCharacter = 0x1D,
+    /// Response to ImportTransferredCharacter
+    TransferredCharacterImported = 0x1E,
 }
 
 #[binrw]
@@ -151,6 +212,67 @@ pub enum CustomIpcData {
     },
     #[br(pre_assert(*magic == CustomIpcType::CharacterRemade))]
     CharacterRemade { content_id: u64 },
+    #[br(pre_assert(*magic == CustomIpcType::DeleteServiceAccount))]
+    DeleteServiceAccount { service_account_id: u32 },
+    #[br(pre_assert(*magic == CustomIpcType::ServiceAccountDeleted))]
+    ServiceAccountDeleted { num_deleted: u32 },
+    #[br(pre_assert(*magic == CustomIpcType::RenameCharacter))]
+    RenameCharacter {
+        content_id: u64,
+        #[bw(pad_size_to = CHAR_NAME_MAX_LENGTH)]
+        #[br(count = CHAR_NAME_MAX_LENGTH)]
+        #[br(map = read_string)]
+        #[bw(map = write_string)]
+        name: String,
+    },
+    #[br(pre_assert(*magic == CustomIpcType::CharacterRenamed))]
+    CharacterRenamed {
+        /// False if the name was already taken.
+        #[br(map = read_bool_from::<u8>)]
+        #[bw(map = write_bool_as::<u8>)]
+        renamed: bool,
+    },
+    #[br(pre_assert(*magic == CustomIpcType::Authenticate))]
+    Authenticate {
+        #[bw(pad_size_to = INTERNAL_SECRET_MAX_LENGTH)]
+        #[br(count = INTERNAL_SECRET_MAX_LENGTH)]
+        #[br(map = read_string)]
+        #[bw(map = write_string)]
+        secret: String,
+    },
+    #[br(pre_assert(*magic == CustomIpcType::Authenticated))]
+    Authenticated {
+        /// False if the secret was wrong, the connection is closed afterwards.
+        #[br(map = read_bool_from::<u8>)]
+        #[bw(map = write_bool_as::<u8>)]
+        success: bool,
+    },
+    #[br(pre_assert(*magic == CustomIpcType::CharacterImported))]
+    CharacterImported { service_account_id: u32 },
+    #[br(pre_assert(*magic == CustomIpcType::ExportCharacter))]
+    ExportCharacter { content_id: u64 },
+    #[br(pre_assert(*magic == CustomIpcType::CharacterExported))]
+    CharacterExported {
+        /// False if there's no such character on this world.
+        #[br(map = read_bool_from::<u8>)]
+        #[bw(map = write_bool_as::<u8>)]
+        found: bool,
+        character: CharacterTransfer,
+    },
+    #[br(pre_assert(*magic == CustomIpcType::ImportTransferredCharacter))]
+    ImportTransferredCharacter {
+        service_account_id: u32,
+        character: CharacterTransfer,
+    },
+    #[br(pre_assert(*magic == CustomIpcType::TransferredCharacterImported))]
+    TransferredCharacterImported {
+        /// False if the name is already taken on this world, in which case the ids are meaningless.
+        #[br(map = read_bool_from::<u8>)]
+        #[bw(map = write_bool_as::<u8>)]
+        imported: bool,
+        content_id: u64,
+        actor_id: u32,
+    },
 }
 
 impl Default for CustomIpcData {
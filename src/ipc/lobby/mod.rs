@@ -89,9 +89,10 @@ pub enum ClientLobbyIpcData {
     /// Sent by the client when it requests the character list in the lobby.
     #[br(pre_assert(*magic == ClientLobbyIpcType::ServiceLogin))]
     ServiceLogin {
-        #[brw(pad_before = 16)]
+        /// Index into the service account list from LoginReply.
+        #[brw(pad_after = 15)] // TODO: what is in here?
+        account_index: u8,
         sequence: u64,
-        // TODO: what is in here?
     },
     /// Sent by the client when it requests to enter a world.
     #[br(pre_assert(*magic == ClientLobbyIpcType::GameLogin))]
@@ -7,6 +7,7 @@ use crate::common::{read_string, write_string};
 pub struct Server {
     pub id: u16,
     pub index: u16,
+    /// The world's status, see the `FLAG_` constants.
     pub flags: u32,
     #[brw(pad_before = 4)]
     #[brw(pad_after = 4)]
@@ -18,6 +19,11 @@ pub struct Server {
     pub name: String,
 }
 
+impl Server {
+    /// The world can't take new characters. This is the only status bit we know of, so it's also used for worlds that are down.
+    pub const FLAG_NOT_ACCEPTING_CHARACTERS: u32 = 0x2;
+}
+
 #[binrw]
 #[derive(Debug, Clone, Default)]
 pub struct DistWorldInfo {
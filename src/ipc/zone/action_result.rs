@@ -17,9 +17,12 @@ pub enum DamageKind {
 #[binrw]
 #[derive(Debug, Eq, PartialEq, Clone, Copy, Default)]
 pub enum EffectKind {
+    /// Used to fill out the unused effect slots.
     #[default]
     #[brw(magic = 0u8)]
-    Miss, // FIXME: is this correct?
+    Nothing,
+    #[brw(magic = 1u8)]
+    Miss,
     #[brw(magic = 3u8)]
     Damage {
         damage_kind: DamageKind,
@@ -37,6 +40,25 @@ pub enum EffectKind {
         unk4: u8,
         amount: u16,
     },
+    #[brw(magic = 4u8)]
+    Heal {
+        unk1: u8,
+        unk2: u8,
+        unk3: u8,
+        unk4: u8,
+        unk5: u8,
+        amount: u16,
+    },
+    /// Gives the target a status effect. How long it lasts isn't part of this, and is sent in the status effect list instead.
+    #[brw(magic = 14u8)]
+    GainEffect {
+        unk1: u8,
+        unk2: u8,
+        unk3: u8,
+        unk4: u8,
+        unk5: u8,
+        effect_id: u16,
+    },
     #[brw(magic = 27u8)]
     BeginCombo,
 }
@@ -185,6 +207,9 @@ mod tests {
         // effect 1: start action combo
         assert_eq!(action_result.effects[1].kind, EffectKind::BeginCombo);
 
+        // the rest are unused
+        assert_eq!(action_result.effects[2].kind, EffectKind::Nothing);
+
         assert_eq!(
             action_result.target_id_again.object_id,
             ObjectId(0x40070E42)
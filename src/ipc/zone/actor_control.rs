@@ -2,7 +2,7 @@ use binrw::binrw;
 
 use crate::common::{read_bool_from, write_bool_as};
 
-use super::OnlineStatus;
+use super::{CharacterMode, OnlineStatus};
 
 // TODO: these are all somewhat related, but maybe should be separated?
 
@@ -93,6 +93,20 @@ pub enum ActorControlCategory {
     },
     #[brw(magic = 0xFu16)]
     CancelCast {},
+    /// Changes what an actor is doing, e.g. being dead.
+    #[brw(magic = 0x2u16)]
+    SetMode {
+        #[brw(pad_before = 2)] // padding
+        #[brw(pad_after = 3)]
+        mode: CharacterMode,
+        mode_arg: u32,
+    },
+    /// Plays the death animation for an actor.
+    #[brw(magic = 0xEu16)]
+    Kill {
+        #[brw(pad_before = 2)] // padding
+        animation_id: u32,
+    },
     #[brw(magic = 0x396u16)]
     ToggleOrchestrionUnlock {
         #[brw(pad_before = 2)] // padding
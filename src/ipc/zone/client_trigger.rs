@@ -21,6 +21,12 @@ pub enum ClientTriggerCommand {
     },
     #[brw(magic = 0xC81u16)]
     Unk1 {},
+    /// The player was knocked out, and chose to return to their home point.
+    #[brw(magic = 0xC8u16)]
+    Revive {
+        #[brw(pad_before = 2)] // padding
+        unk1: u32,
+    },
     #[brw(magic = 0xC9u16)]
     FinishZoning {},
     /// The player begins an emote.
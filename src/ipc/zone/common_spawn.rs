@@ -74,7 +74,7 @@ pub enum ObjectKind {
 #[binrw]
 #[brw(little)]
 #[brw(repr = u8)]
-#[derive(Debug, Clone, Default, PartialEq)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
 pub enum CharacterMode {
     None = 0x0,
     #[default]
@@ -1,14 +1,12 @@
-use std::cmp::min;
+use std::{cmp::min, collections::HashMap};
 
-use tokio::{io::AsyncReadExt, net::TcpStream};
+use tokio::net::TcpStream;
 
 use crate::{
-    RECEIVE_BUFFER_SIZE,
     blowfish::Blowfish,
     common::timestamp_secs,
-    config::get_config,
+    config::{WorldListingConfig, get_config},
     opcodes::ServerLobbyIpcType,
-    packet::oodle::OodleNetwork,
     packet::{
         CompressionType, ConnectionType, PacketSegment, PacketState, SegmentData, SegmentType,
         generate_encryption_key, parse_packet, send_packet,
@@ -22,6 +20,16 @@ use crate::ipc::lobby::{
     ServiceLoginReply,
 };
 
+use super::{is_world_online, send_custom_world_packet};
+
+/// Generic lobby error, for when an action isn't supported (yet) or the world server can't be reached.
+const GENERIC_LOBBY_ERROR: u32 = 1012;
+/// The EXD error shown alongside `GENERIC_LOBBY_ERROR`.
+const GENERIC_LOBBY_EXD_ERROR: u16 = 13101;
+
+/// The most characters that can be shown in the lobby, across all worlds.
+const MAX_CHARACTERS: usize = 8;
+
 /// Represents a single connection between an instance of the client and the lobby server.
 pub struct LobbyConnection {
     pub socket: TcpStream,
@@ -32,7 +40,13 @@ pub struct LobbyConnection {
 
     pub stored_character_creation_name: String,
 
-    pub world_name: String,
+    pub stored_character_creation_world: u16,
+
+    /// Every world listed in the lobby, with their names filled in.
+    pub worlds: Vec<WorldListingConfig>,
+
+    /// Which world each character on the selected service account lives on.
+    pub character_worlds: HashMap<u64, u16>,
 
     pub service_accounts: Vec<ServiceAccount>,
 
@@ -107,21 +121,30 @@ impl LobbyConnection {
         let mut packets = Vec::new();
         // send them the server list
         {
-            let config = get_config();
-
-            let mut servers = [Server {
-                id: config.world.world_id,
-                name: self.world_name.clone(),
-                ..Default::default()
-            }]
-            .to_vec();
+            let mut servers: Vec<Server> = self
+                .worlds
+                .iter()
+                .enumerate()
+                .map(|(index, world)| Server {
+                    id: world.id,
+                    index: index as u16,
+                    flags: if is_world_online(world.id) {
+                        0
+                    } else {
+                        Server::FLAG_NOT_ACCEPTING_CHARACTERS
+                    },
+                    name: world.name.clone(),
+                    ..Default::default()
+                })
+                .collect();
+            let num_servers = servers.len() as u32;
             // add any empty boys
             servers.resize(6, Server::default());
 
             let lobby_server_list = ServerLobbyIpcData::DistWorldInfo(DistWorldInfo {
                 sequence: 0,
                 unk1: 1,
-                num_servers: 1,
+                num_servers,
                 servers,
                 ..Default::default()
             });
@@ -169,25 +192,56 @@ impl LobbyConnection {
         )
         .await;
 
-        // now send them the character list
+        // now send them the character list, gathered from every world
         {
-            let charlist_request = CustomIpcSegment {
-                op_code: CustomIpcType::RequestCharacterList,
-                data: CustomIpcData::RequestCharacterList {
-                    service_account_id: self.selected_service_account.unwrap(),
-                },
-                ..Default::default()
-            };
+            let mut characters = Vec::new();
+            self.character_worlds.clear();
+
+            for world_id in self.worlds.iter().map(|world| world.id).collect::<Vec<_>>() {
+                let charlist_request = CustomIpcSegment {
+                    op_code: CustomIpcType::RequestCharacterList,
+                    data: CustomIpcData::RequestCharacterList {
+                        service_account_id: self.selected_service_account.unwrap(),
+                    },
+                    ..Default::default()
+                };
 
-            let name_response = send_custom_world_packet(charlist_request)
-                .await
-                .expect("Failed to get name request packet!");
-            let CustomIpcData::RequestCharacterListRepsonse { characters } = &name_response.data
-            else {
-                panic!("Unexpedted custom IPC type!")
-            };
+                let world_characters =
+                    match send_custom_world_packet(world_id, charlist_request).await {
+                        Ok(CustomIpcSegment {
+                            data: CustomIpcData::RequestCharacterListRepsonse { characters },
+                            ..
+                        }) => characters,
+                        Ok(_) => {
+                            tracing::warn!("World {world_id} sent an unexpected response!");
+                            continue;
+                        }
+                        Err(err) => {
+                            tracing::warn!(
+                                "Couldn't get the character list from world {world_id}: {err}"
+                            );
+                            continue;
+                        }
+                    };
 
-            let mut characters = characters.to_vec();
+                for character in world_characters {
+                    self.character_worlds.insert(character.content_id, world_id);
+                    characters.push(character);
+                }
+            }
+
+            if characters.len() > MAX_CHARACTERS {
+                tracing::warn!(
+                    "Service account has {} characters, but only {MAX_CHARACTERS} can be shown!",
+                    characters.len()
+                );
+                characters.truncate(MAX_CHARACTERS);
+            }
+
+            // each world numbers their own characters, but the client expects them to be unique
+            for (index, character) in characters.iter_mut().enumerate() {
+                character.index = index as u8;
+            }
 
             for i in 0..4 {
                 let mut characters_in_packet = Vec::new();
@@ -208,7 +262,7 @@ impl LobbyConnection {
                         remaining_days: 30,
                         days_to_next_rank: 0,
                         unk8: 8,
-                        max_characters_on_world: 8,
+                        max_characters_on_world: MAX_CHARACTERS as u8,
                         entitled_expansion: 5,
                         characters: characters_in_packet,
                         ..Default::default()
@@ -240,17 +294,85 @@ impl LobbyConnection {
         }
     }
 
-    /// Send the host information for the world server to the client.
-    pub async fn send_enter_world(&mut self, sequence: u64, content_id: u64, actor_id: u32) {
-        let config = get_config();
+    /// Finds the name of a world listed in the lobby.
+    fn world_name(&self, world_id: u16) -> String {
+        self.worlds
+            .iter()
+            .find(|world| world.id == world_id)
+            .map(|world| world.name.clone())
+            .unwrap_or_default()
+    }
+
+    /// Finds the world a character on the selected service account lives on.
+    /// If it isn't one of theirs, the client is sent an error and None is returned.
+    async fn owned_character_world(&mut self, sequence: u64, content_id: u64) -> Option<u16> {
+        let world_id = self.character_worlds.get(&content_id).copied();
+        if world_id.is_none() {
+            tracing::warn!(
+                "Client tried to use character {content_id}, which doesn't belong to their service account!"
+            );
+            self.send_error(sequence, GENERIC_LOBBY_ERROR, GENERIC_LOBBY_EXD_ERROR)
+                .await;
+        }
+
+        world_id
+    }
+
+    /// Removes a character that was just imported into a world, when the rest of moving them failed.
+    async fn undo_character_import(&self, world_id: u16, content_id: u64) {
+        let ipc_segment = CustomIpcSegment {
+            op_code: CustomIpcType::DeleteCharacter,
+            data: CustomIpcData::DeleteCharacter { content_id },
+            ..Default::default()
+        };
+
+        match send_custom_world_packet(world_id, ipc_segment).await {
+            Ok(CustomIpcSegment {
+                data: CustomIpcData::CharacterDeleted { deleted: 1 },
+                ..
+            }) => {}
+            Ok(_) => tracing::error!(
+                "World {world_id} didn't remove {content_id} after a failed move, they now exist on two worlds and have to be deleted from one by hand!"
+            ),
+            Err(err) => tracing::error!(
+                "Couldn't remove {content_id} from world {world_id} after a failed move, they now exist on two worlds and have to be deleted from one by hand: {err}"
+            ),
+        }
+    }
 
+    /// Sends a request to a world server, and returns its response. If that fails, the client is sent an error instead.
+    async fn request_world(
+        &mut self,
+        sequence: u64,
+        world_id: u16,
+        segment: CustomIpcSegment,
+    ) -> Option<CustomIpcData> {
+        match send_custom_world_packet(world_id, segment).await {
+            Ok(response) => Some(response.data),
+            Err(err) => {
+                tracing::warn!("Request to world {world_id} failed: {err}");
+                self.send_error(sequence, GENERIC_LOBBY_ERROR, GENERIC_LOBBY_EXD_ERROR)
+                    .await;
+                None
+            }
+        }
+    }
+
+    /// Send the host information for the world server to the client.
+    pub async fn send_enter_world(
+        &mut self,
+        sequence: u64,
+        content_id: u64,
+        actor_id: u32,
+        world: &WorldListingConfig,
+    ) {
         let enter_world = ServerLobbyIpcData::GameLoginReply {
             sequence,
             actor_id,
             content_id,
             token: String::new(),
-            port: config.world.port,
-            host: config.world.server_name,
+            port: world.port,
+            host: world.server_name.clone(),
         };
 
         let ipc = ServerLobbyIpcSegment {
@@ -294,6 +416,22 @@ impl LobbyConnection {
     }
 
     pub async fn handle_character_action(&mut self, character_action: &CharaMake) {
+        // new characters go to the world the player picked, everything else to the world the character lives on
+        let world_id = match character_action.action {
+            LobbyCharacterActionKind::ReserveName => character_action.world_id,
+            LobbyCharacterActionKind::Create => self.stored_character_creation_world,
+            _ => {
+                let Some(world_id) = self
+                    .owned_character_world(character_action.sequence, character_action.content_id)
+                    .await
+                else {
+                    return;
+                };
+                world_id
+            }
+        };
+        let world_name = self.world_name(world_id);
+
         match &character_action.action {
             LobbyCharacterActionKind::ReserveName => {
                 tracing::info!(
@@ -310,17 +448,18 @@ impl LobbyConnection {
                     ..Default::default()
                 };
 
-                let name_response = send_custom_world_packet(name_request)
+                let Some(CustomIpcData::NameIsAvailableResponse { free }) = self
+                    .request_world(character_action.sequence, world_id, name_request)
                     .await
-                    .expect("Failed to get name request packet!");
-                let CustomIpcData::NameIsAvailableResponse { free } = &name_response.data else {
-                    panic!("Unexpedted custom IPC type!")
+                else {
+                    return;
                 };
 
                 tracing::info!("Is name free? {free}");
 
-                if *free {
+                if free {
                     self.stored_character_creation_name = character_action.name.clone();
+                    self.stored_character_creation_world = world_id;
 
                     let ipc = ServerLobbyIpcSegment {
                         op_code: ServerLobbyIpcType::CharaMakeReply,
@@ -331,8 +470,8 @@ impl LobbyConnection {
                             action: LobbyCharacterActionKind::ReserveName,
                             details: CharacterDetails {
                                 character_name: character_action.name.clone(),
-                                origin_server_name: self.world_name.clone(),
-                                current_server_name: self.world_name.clone(),
+                                origin_server_name: world_name.clone(),
+                                current_server_name: world_name.clone(),
                                 ..Default::default()
                             },
                         },
@@ -369,11 +508,8 @@ impl LobbyConnection {
             LobbyCharacterActionKind::Create => {
                 tracing::info!("Player is creating a new character!");
 
-                let our_actor_id;
-                let our_content_id;
-
                 // tell the world server to create this character
-                {
+                let (our_actor_id, our_content_id) = {
                     let ipc_segment = CustomIpcSegment {
                         op_code: CustomIpcType::RequestCreateCharacter,
                         data: CustomIpcData::RequestCreateCharacter {
@@ -384,23 +520,25 @@ impl LobbyConnection {
                         ..Default::default()
                     };
 
-                    let response_segment = send_custom_world_packet(ipc_segment).await.unwrap();
-                    match &response_segment.data {
-                        CustomIpcData::CharacterCreated {
-                            actor_id,
-                            content_id,
-                        } => {
-                            our_actor_id = *actor_id;
-                            our_content_id = *content_id;
-                        }
-                        _ => panic!("Unexpected custom IPC packet type here!"),
-                    }
-                }
+                    let Some(CustomIpcData::CharacterCreated {
+                        actor_id,
+                        content_id,
+                    }) = self
+                        .request_world(character_action.sequence, world_id, ipc_segment)
+                        .await
+                    else {
+                        return;
+                    };
+
+                    (actor_id, content_id)
+                };
 
                 tracing::info!(
                     "Got new player info from world server: {our_content_id} {our_actor_id}"
                 );
 
+                self.character_worlds.insert(our_content_id, world_id);
+
                 // a slightly different character created packet now
                 {
                     let ipc = ServerLobbyIpcSegment {
@@ -414,8 +552,8 @@ impl LobbyConnection {
                                 actor_id: our_actor_id,
                                 content_id: our_content_id,
                                 character_name: character_action.name.clone(),
-                                origin_server_name: self.world_name.clone(),
-                                current_server_name: self.world_name.clone(),
+                                origin_server_name: world_name.clone(),
+                                current_server_name: world_name.clone(),
                                 ..Default::default()
                             },
                         },
@@ -430,7 +568,62 @@ impl LobbyConnection {
                     .await;
                 }
             }
-            LobbyCharacterActionKind::Rename => todo!(),
+            LobbyCharacterActionKind::Rename => {
+                tracing::info!(
+                    "Player is renaming {} to {}!",
+                    character_action.content_id,
+                    character_action.name
+                );
+
+                // tell the world server to rename them, which fails if the name is taken or invalid
+                let ipc_segment = CustomIpcSegment {
+                    op_code: CustomIpcType::RenameCharacter,
+                    data: CustomIpcData::RenameCharacter {
+                        content_id: character_action.content_id,
+                        name: character_action.name.clone(),
+                    },
+                    ..Default::default()
+                };
+
+                let Some(CustomIpcData::CharacterRenamed { renamed }) = self
+                    .request_world(character_action.sequence, world_id, ipc_segment)
+                    .await
+                else {
+                    return;
+                };
+
+                if !renamed {
+                    // same error as when reserving a name that's taken
+                    self.send_error(character_action.sequence, 0x00000bdb, 0x32cc)
+                        .await;
+                    return;
+                }
+
+                let ipc = ServerLobbyIpcSegment {
+                    op_code: ServerLobbyIpcType::CharaMakeReply,
+                    data: ServerLobbyIpcData::CharaMakeReply {
+                        sequence: character_action.sequence + 1,
+                        unk1: 0x1,
+                        unk2: 0x1,
+                        action: LobbyCharacterActionKind::Rename,
+                        details: CharacterDetails {
+                            content_id: character_action.content_id,
+                            character_name: character_action.name.clone(),
+                            origin_server_name: world_name.clone(),
+                            current_server_name: world_name.clone(),
+                            ..Default::default()
+                        },
+                    },
+                    ..Default::default()
+                };
+
+                self.send_segment(PacketSegment {
+                    segment_type: SegmentType::Ipc,
+                    data: SegmentData::Ipc { data: ipc },
+                    ..Default::default()
+                })
+                .await;
+            }
             LobbyCharacterActionKind::Delete => {
                 // tell the world server to yeet this guy
                 {
@@ -442,11 +635,16 @@ impl LobbyConnection {
                         ..Default::default()
                     };
 
-                    let _ = send_custom_world_packet(ipc_segment).await.unwrap();
-
-                    // we intentionally don't care about the response right now, it's not expected to fail
+                    let Some(CustomIpcData::CharacterDeleted { .. }) = self
+                        .request_world(character_action.sequence, world_id, ipc_segment)
+                        .await
+                    else {
+                        return;
+                    };
                 }
 
+                self.character_worlds.remove(&character_action.content_id);
+
                 // send a confirmation that the deletion was successful
                 {
                     let ipc = ServerLobbyIpcSegment {
@@ -460,8 +658,8 @@ impl LobbyConnection {
                                 actor_id: 0, // TODO: fill maybe?
                                 content_id: character_action.content_id,
                                 character_name: character_action.name.clone(),
-                                origin_server_name: self.world_name.clone(),
-                                current_server_name: self.world_name.clone(),
+                                origin_server_name: world_name.clone(),
+                                current_server_name: world_name.clone(),
                                 ..Default::default()
                             },
                         },
@@ -476,8 +674,178 @@ impl LobbyConnection {
                     .await;
                 }
             }
-            LobbyCharacterActionKind::Move => todo!(),
-            LobbyCharacterActionKind::RemakeRetainer => todo!(),
+            LobbyCharacterActionKind::Move => {
+                // the world they're moving to, while world_id is the one they currently live on
+                let target_world_id = character_action.world_id;
+                if target_world_id == world_id
+                    || !self.worlds.iter().any(|world| world.id == target_world_id)
+                {
+                    tracing::warn!(
+                        "Can't move {} from world {world_id} to {target_world_id}!",
+                        character_action.content_id
+                    );
+                    self.send_error(
+                        character_action.sequence,
+                        GENERIC_LOBBY_ERROR,
+                        GENERIC_LOBBY_EXD_ERROR,
+                    )
+                    .await;
+                    return;
+                }
+
+                tracing::info!(
+                    "Moving {} from world {world_id} to {target_world_id}!",
+                    character_action.content_id
+                );
+
+                // grab everything about them from their current world
+                let ipc_segment = CustomIpcSegment {
+                    op_code: CustomIpcType::ExportCharacter,
+                    data: CustomIpcData::ExportCharacter {
+                        content_id: character_action.content_id,
+                    },
+                    ..Default::default()
+                };
+
+                let Some(response) = self
+                    .request_world(character_action.sequence, world_id, ipc_segment)
+                    .await
+                else {
+                    return;
+                };
+                let CustomIpcData::CharacterExported {
+                    found: true,
+                    character,
+                } = response
+                else {
+                    tracing::warn!(
+                        "World {world_id} doesn't know about {}!",
+                        character_action.content_id
+                    );
+                    self.send_error(
+                        character_action.sequence,
+                        GENERIC_LOBBY_ERROR,
+                        GENERIC_LOBBY_EXD_ERROR,
+                    )
+                    .await;
+                    return;
+                };
+
+                // add them to the new world, which fails if their name is taken there
+                let ipc_segment = CustomIpcSegment {
+                    op_code: CustomIpcType::ImportTransferredCharacter,
+                    data: CustomIpcData::ImportTransferredCharacter {
+                        service_account_id: self.selected_service_account.unwrap(),
+                        character,
+                    },
+                    ..Default::default()
+                };
+
+                let Some(CustomIpcData::TransferredCharacterImported {
+                    imported,
+                    content_id,
+                    actor_id,
+                }) = self
+                    .request_world(character_action.sequence, target_world_id, ipc_segment)
+                    .await
+                else {
+                    return;
+                };
+
+                if !imported {
+                    // same error as when reserving a name that's taken
+                    self.send_error(character_action.sequence, 0x00000bdb, 0x32cc)
+                        .await;
+                    return;
+                }
+
+                // only now is it safe to remove them from their old world
+                let ipc_segment = CustomIpcSegment {
+                    op_code: CustomIpcType::DeleteCharacter,
+                    data: CustomIpcData::DeleteCharacter {
+                        content_id: character_action.content_id,
+                    },
+                    ..Default::default()
+                };
+
+                let deleted = match send_custom_world_packet(world_id, ipc_segment).await {
+                    Ok(CustomIpcSegment {
+                        data: CustomIpcData::CharacterDeleted { deleted: 1 },
+                        ..
+                    }) => true,
+                    Ok(_) => {
+                        tracing::warn!(
+                            "World {world_id} didn't delete {} after they were moved!",
+                            character_action.content_id
+                        );
+                        false
+                    }
+                    Err(err) => {
+                        tracing::warn!(
+                            "Couldn't delete {} from world {world_id} after they were moved: {err}",
+                            character_action.content_id
+                        );
+                        false
+                    }
+                };
+
+                if !deleted {
+                    // undo the import, so they don't end up on both worlds
+                    self.undo_character_import(target_world_id, content_id)
+                        .await;
+                    self.send_error(
+                        character_action.sequence,
+                        GENERIC_LOBBY_ERROR,
+                        GENERIC_LOBBY_EXD_ERROR,
+                    )
+                    .await;
+                    return;
+                }
+
+                self.character_worlds.remove(&character_action.content_id);
+                self.character_worlds.insert(content_id, target_world_id);
+
+                let target_world_name = self.world_name(target_world_id);
+
+                let ipc = ServerLobbyIpcSegment {
+                    op_code: ServerLobbyIpcType::CharaMakeReply,
+                    data: ServerLobbyIpcData::CharaMakeReply {
+                        sequence: character_action.sequence + 1,
+                        unk1: 0x1,
+                        unk2: 0x1,
+                        action: LobbyCharacterActionKind::Move,
+                        details: CharacterDetails {
+                            actor_id,
+                            content_id,
+                            character_name: character_action.name.clone(),
+                            origin_server_name: target_world_name.clone(),
+                            current_server_name: target_world_name,
+                            ..Default::default()
+                        },
+                    },
+                    ..Default::default()
+                };
+
+                self.send_segment(PacketSegment {
+                    segment_type: SegmentType::Ipc,
+                    data: SegmentData::Ipc { data: ipc },
+                    ..Default::default()
+                })
+                .await;
+            }
+            LobbyCharacterActionKind::RemakeRetainer
+            | LobbyCharacterActionKind::SettingsUploadBegin
+            | LobbyCharacterActionKind::SettingsUpload
+            | LobbyCharacterActionKind::WorldVisit
+            | LobbyCharacterActionKind::DataCenterToken
+            | LobbyCharacterActionKind::Request => {
+                // TODO: neither the replies nor the errors retail sends for these have been captured, and the uploaded settings
+                // haven't been figured out, so they go unanswered instead of guessing
+                tracing::warn!(
+                    "Character action {:?} isn't supported yet!",
+                    character_action.action
+                );
+            }
             LobbyCharacterActionKind::RemakeChara => {
                 // tell the world server to turn this guy into a catgirl
                 {
@@ -490,9 +858,12 @@ impl LobbyConnection {
                         ..Default::default()
                     };
 
-                    let _ = send_custom_world_packet(ipc_segment).await.unwrap();
-
-                    // we intentionally don't care about the response right now, it's not expected to fail
+                    let Some(CustomIpcData::CharacterRemade { .. }) = self
+                        .request_world(character_action.sequence, world_id, ipc_segment)
+                        .await
+                    else {
+                        return;
+                    };
                 }
 
                 // send a confirmation that the remakewas successful
@@ -508,8 +879,8 @@ impl LobbyConnection {
                                 actor_id: 0, // TODO: fill maybe?
                                 content_id: character_action.content_id,
                                 character_name: character_action.name.clone(),
-                                origin_server_name: self.world_name.clone(),
-                                current_server_name: self.world_name.clone(),
+                                origin_server_name: world_name.clone(),
+                                current_server_name: world_name.clone(),
                                 ..Default::default()
                             },
                         },
@@ -524,56 +895,6 @@ impl LobbyConnection {
                     .await;
                 }
             }
-            LobbyCharacterActionKind::SettingsUploadBegin => todo!(),
-            LobbyCharacterActionKind::SettingsUpload => todo!(),
-            LobbyCharacterActionKind::WorldVisit => todo!(),
-            LobbyCharacterActionKind::DataCenterToken => todo!(),
-            LobbyCharacterActionKind::Request => todo!(),
         }
     }
 }
-
-/// Sends a custom IPC packet to the world server, meant for private server-to-server communication.
-/// Returns the first custom IPC segment returned.
-pub async fn send_custom_world_packet(segment: CustomIpcSegment) -> Option<CustomIpcSegment> {
-    let config = get_config();
-
-    let addr = config.world.get_public_socketaddr();
-
-    let mut stream = TcpStream::connect(addr).await.unwrap();
-
-    let mut packet_state = PacketState {
-        client_key: None,
-        serverbound_oodle: OodleNetwork::new(),
-        clientbound_oodle: OodleNetwork::new(),
-    };
-
-    let segment: PacketSegment<CustomIpcSegment> = PacketSegment {
-        segment_type: SegmentType::KawariIpc,
-        data: SegmentData::KawariIpc { data: segment },
-        ..Default::default()
-    };
-
-    send_packet(
-        &mut stream,
-        &mut packet_state,
-        ConnectionType::None,
-        CompressionType::Uncompressed,
-        &[segment],
-    )
-    .await;
-
-    // read response
-    let mut buf = vec![0; RECEIVE_BUFFER_SIZE];
-    let n = stream.read(&mut buf).await.expect("Failed to read data!");
-    if n != 0 {
-        let (segments, _) = parse_packet::<CustomIpcSegment>(&buf[..n], &mut packet_state);
-
-        return match &segments[0].data {
-            SegmentData::KawariIpc { data } => Some(data.clone()),
-            _ => None,
-        };
-    }
-
-    None
-}
@@ -1,2 +1,7 @@
 mod connection;
-pub use connection::{LobbyConnection, send_custom_world_packet};
+pub use connection::LobbyConnection;
+
+mod world_connection;
+pub use world_connection::{
+    WorldIpcError, is_world_online, send_custom_world_packet, watch_world_status,
+};
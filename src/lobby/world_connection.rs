@@ -0,0 +1,246 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        LazyLock, Mutex,
+        atomic::{AtomicU32, Ordering},
+    },
+    time::Duration,
+};
+
+use tokio::net::TcpStream;
+
+use crate::{
+    config::{WorldListingConfig, check_internal_secret, get_config},
+    ipc::kawari::{CustomIpcData, CustomIpcSegment, CustomIpcType},
+    packet::{
+        CompressionType, ConnectionType, PacketSegment, PacketState, SegmentData, SegmentType,
+        oodle::OodleNetwork, parse_packet, read_packet, send_packet,
+    },
+};
+
+/// How long to wait on a world server, whether connecting or waiting for a response.
+const WORLD_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How often every world is checked to see if it's up.
+const WORLD_STATUS_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How many unused connections are kept around for each world.
+const MAX_IDLE_CONNECTIONS: usize = 4;
+
+/// Authenticated connections that aren't being used right now, keyed by world id.
+static IDLE_CONNECTIONS: LazyLock<Mutex<HashMap<u16, Vec<WorldConnection>>>> =
+    LazyLock::new(Default::default);
+
+static NEXT_REQUEST_ID: AtomicU32 = AtomicU32::new(1);
+
+/// Whether each world was up the last time it was checked, keyed by world id.
+static WORLD_STATUS: LazyLock<Mutex<HashMap<u16, bool>>> = LazyLock::new(Default::default);
+
+/// Why a request to a world server failed.
+#[derive(Debug)]
+pub enum WorldIpcError {
+    /// The world isn't in the config.
+    UnknownWorld(u16),
+    /// Couldn't connect, or the connection broke.
+    Io(std::io::Error),
+    /// The world server didn't accept our secret.
+    AuthenticationFailed,
+    /// The secret configured for the world can't be sent.
+    InvalidSecret(String),
+    /// The world server took too long to respond.
+    TimedOut,
+}
+
+impl std::fmt::Display for WorldIpcError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WorldIpcError::UnknownWorld(world_id) => write!(f, "world {world_id} isn't configured"),
+            WorldIpcError::Io(err) => write!(f, "{err}"),
+            WorldIpcError::AuthenticationFailed => {
+                write!(f, "the world server rejected our internal secret")
+            }
+            WorldIpcError::InvalidSecret(reason) => write!(f, "{reason}"),
+            WorldIpcError::TimedOut => write!(f, "the world server took too long to respond"),
+        }
+    }
+}
+
+impl std::error::Error for WorldIpcError {}
+
+impl From<std::io::Error> for WorldIpcError {
+    fn from(err: std::io::Error) -> Self {
+        WorldIpcError::Io(err)
+    }
+}
+
+/// An authenticated connection to a world server's internal port.
+struct WorldConnection {
+    socket: TcpStream,
+    state: PacketState,
+}
+
+impl WorldConnection {
+    async fn connect(world: &WorldListingConfig) -> Result<Self, WorldIpcError> {
+        // it would be cut off when sent, and the world server wouldn't accept it anyway
+        check_internal_secret(&world.internal_secret).map_err(WorldIpcError::InvalidSecret)?;
+
+        let mut connection = Self {
+            socket: TcpStream::connect(world.get_internal_socketaddr()).await?,
+            state: PacketState {
+                client_key: None,
+                serverbound_oodle: OodleNetwork::new(),
+                clientbound_oodle: OodleNetwork::new(),
+            },
+        };
+
+        let response = connection
+            .request(CustomIpcSegment {
+                op_code: CustomIpcType::Authenticate,
+                data: CustomIpcData::Authenticate {
+                    secret: world.internal_secret.clone(),
+                },
+                ..Default::default()
+            })
+            .await?;
+
+        match response.data {
+            CustomIpcData::Authenticated { success: true } => Ok(connection),
+            _ => Err(WorldIpcError::AuthenticationFailed),
+        }
+    }
+
+    /// Sends a request, and waits for the response with the same request id.
+    async fn request(
+        &mut self,
+        mut segment: CustomIpcSegment,
+    ) -> Result<CustomIpcSegment, WorldIpcError> {
+        let request_id = NEXT_REQUEST_ID.fetch_add(1, Ordering::Relaxed);
+        segment.set_request_id(request_id);
+
+        send_packet(
+            &mut self.socket,
+            &mut self.state,
+            ConnectionType::None,
+            CompressionType::Uncompressed,
+            &[PacketSegment {
+                segment_type: SegmentType::KawariIpc,
+                data: SegmentData::KawariIpc { data: segment },
+                ..Default::default()
+            }],
+        )
+        .await;
+
+        loop {
+            let data = read_packet(&mut self.socket).await?;
+            let (segments, _) = parse_packet::<CustomIpcSegment>(&data, &mut self.state);
+
+            for segment in segments {
+                match segment.data {
+                    SegmentData::KawariIpc { data } if data.request_id() == request_id => {
+                        return Ok(data);
+                    }
+                    SegmentData::KawariIpc { data } => {
+                        tracing::warn!("Ignoring response to an old request {}", data.request_id())
+                    }
+                    _ => tracing::warn!("Ignoring non-custom IPC segment from a world server"),
+                }
+            }
+        }
+    }
+}
+
+fn take_idle_connection(world_id: u16) -> Option<WorldConnection> {
+    IDLE_CONNECTIONS
+        .lock()
+        .unwrap()
+        .get_mut(&world_id)
+        .and_then(|connections| connections.pop())
+}
+
+fn return_idle_connection(world_id: u16, connection: WorldConnection) {
+    let mut idle_connections = IDLE_CONNECTIONS.lock().unwrap();
+    let connections = idle_connections.entry(world_id).or_default();
+    if connections.len() < MAX_IDLE_CONNECTIONS {
+        connections.push(connection);
+    }
+}
+
+/// Sends a custom IPC packet to a world server, meant for private server-to-server communication.
+/// Returns the world server's response to it.
+pub async fn send_custom_world_packet(
+    world_id: u16,
+    segment: CustomIpcSegment,
+) -> Result<CustomIpcSegment, WorldIpcError> {
+    let world = get_config()
+        .find_world(world_id)
+        .ok_or(WorldIpcError::UnknownWorld(world_id))?;
+
+    // an idle connection may have been closed in the meantime (e.g. the world server restarted), so fall back to a new one
+    if let Some(mut connection) = take_idle_connection(world_id) {
+        match tokio::time::timeout(WORLD_TIMEOUT, connection.request(segment.clone())).await {
+            Ok(Ok(response)) => {
+                return_idle_connection(world_id, connection);
+                return Ok(response);
+            }
+            Ok(Err(WorldIpcError::Io(err))) => {
+                tracing::info!("Idle connection to world {world_id} broke, reconnecting: {err}");
+            }
+            Ok(Err(err)) => return Err(err),
+            Err(_) => return Err(WorldIpcError::TimedOut),
+        }
+    }
+
+    let response = tokio::time::timeout(WORLD_TIMEOUT, async {
+        let mut connection = WorldConnection::connect(&world).await?;
+        let response = connection.request(segment).await?;
+        Ok::<_, WorldIpcError>((connection, response))
+    })
+    .await
+    .map_err(|_| WorldIpcError::TimedOut)?;
+
+    let (connection, response) = response?;
+    return_idle_connection(world_id, connection);
+
+    Ok(response)
+}
+
+/// Whether a world was up the last time it was checked. Worlds that haven't been checked yet count as down.
+pub fn is_world_online(world_id: u16) -> bool {
+    WORLD_STATUS
+        .lock()
+        .unwrap()
+        .get(&world_id)
+        .copied()
+        .unwrap_or(false)
+}
+
+/// Checks every configured world every `WORLD_STATUS_INTERVAL`, by logging into its internal port. Runs forever.
+pub async fn watch_world_status() {
+    let mut interval = tokio::time::interval(WORLD_STATUS_INTERVAL);
+    loop {
+        interval.tick().await;
+
+        // check every world at once, so one that's down doesn't hold up the rest
+        for world in get_config().get_worlds() {
+            tokio::spawn(async move {
+                let online =
+                    match tokio::time::timeout(WORLD_TIMEOUT, WorldConnection::connect(&world))
+                        .await
+                    {
+                        Ok(Ok(connection)) => {
+                            // it's a perfectly good connection, so keep it around for the next request
+                            return_idle_connection(world.id, connection);
+                            true
+                        }
+                        Ok(Err(err)) => {
+                            tracing::debug!("World {} is down: {err}", world.id);
+                            false
+                        }
+                        Err(_) => false,
+                    };
+
+                WORLD_STATUS.lock().unwrap().insert(world.id, online);
+            });
+        }
+    }
+}
@@ -1,8 +1,39 @@
 use std::sync::Mutex;
 
+use argon2::{
+    Argon2, PasswordHash, PasswordHasher, PasswordVerifier,
+    password_hash::{
+        SaltString,
+        rand_core::{OsRng, RngCore},
+    },
+};
 use rusqlite::Connection;
-
-use crate::ipc::lobby::ServiceAccount;
+use subtle::ConstantTimeEq;
+
+use crate::{
+    common::{run_migrations, timestamp_secs},
+    config::get_config,
+    ipc::lobby::ServiceAccount,
+};
+
+/// Every change ever made to the schema, in order. See `run_migrations`.
+const MIGRATIONS: &[&str] = &[
+    // Initial schema
+    "CREATE TABLE IF NOT EXISTS users (id INTEGER PRIMARY KEY, username TEXT, password TEXT);
+    CREATE TABLE IF NOT EXISTS sessions (user_id INTEGER PRIMARY KEY, sid TEXT);
+    CREATE TABLE IF NOT EXISTS service_accounts (id INTEGER PRIMARY KEY, user_id INTEGER);",
+    // Allow more than one session per user, and make them expire. Existing sessions never expired, so they're thrown out.
+    "DROP TABLE sessions;
+    CREATE TABLE sessions (sid TEXT PRIMARY KEY, user_id INTEGER NOT NULL, created INTEGER NOT NULL, expires INTEGER NOT NULL);",
+    // Let users name their service accounts. If it's NULL, a default name is shown instead.
+    "ALTER TABLE service_accounts ADD COLUMN name TEXT;",
+];
+
+/// The most service accounts a user can have, which is also how many the lobby can show.
+const MAX_SERVICE_ACCOUNTS: usize = 8;
+
+/// The longest name a service account can have, since the lobby only has room for this many bytes plus a terminator.
+pub const SERVICE_ACCOUNT_NAME_MAX_LENGTH: usize = 0x44 - 1;
 
 pub struct LoginDatabase {
     connection: Mutex<Connection>,
@@ -15,6 +46,14 @@ pub enum LoginError {
     InternalError,
 }
 
+/// Why a service account couldn't be renamed.
+#[derive(Debug)]
+pub enum ServiceAccountNameError {
+    Empty,
+    TooLong,
+    Taken,
+}
+
 impl Default for LoginDatabase {
     fn default() -> Self {
         Self::new()
@@ -23,26 +62,9 @@ impl Default for LoginDatabase {
 
 impl LoginDatabase {
     pub fn new() -> Self {
-        let connection = Connection::open("login.db").expect("Failed to open database!");
-
-        // Create users table
-        {
-            let query = "CREATE TABLE IF NOT EXISTS users (id INTEGER PRIMARY KEY, username TEXT, password TEXT);";
-            connection.execute(query, ()).unwrap();
-        }
-
-        // Create active sessions table
-        {
-            let query =
-                "CREATE TABLE IF NOT EXISTS sessions (user_id INTEGER PRIMARY KEY, sid TEXT);";
-            connection.execute(query, ()).unwrap();
-        }
+        let mut connection = Connection::open("login.db").expect("Failed to open database!");
 
-        // Create service accounts table
-        {
-            let query = "CREATE TABLE IF NOT EXISTS service_accounts (id INTEGER PRIMARY KEY, user_id INTEGER);";
-            connection.execute(query, ()).unwrap();
-        }
+        run_migrations(&mut connection, MIGRATIONS);
 
         Self {
             connection: Mutex::new(connection),
@@ -70,19 +92,82 @@ impl LoginDatabase {
 
             let query = "INSERT INTO users VALUES (?1, ?2, ?3);";
             connection
-                .execute(query, (user_id, username, password))
+                .execute(query, (user_id, username, Self::hash_password(password)))
                 .expect("Failed to write user to database!");
         }
 
-        // add service account
-        {
-            let connection = self.connection.lock().unwrap();
+        self.add_service_account(user_id);
+    }
 
-            let query = "INSERT INTO service_accounts VALUES (?1, ?2);";
-            connection
-                .execute(query, (Self::generate_account_id(), user_id))
-                .expect("Failed to write service account to database!");
+    /// Adds a new service account to a user, returning its id. Returns None if they already have too many.
+    pub fn add_service_account(&self, user_id: u32) -> Option<u32> {
+        if self.get_service_accounts(user_id).len() >= MAX_SERVICE_ACCOUNTS {
+            return None;
+        }
+
+        let connection = self.connection.lock().unwrap();
+
+        let service_account_id = Self::generate_account_id();
+        connection
+            .execute(
+                "INSERT INTO service_accounts (id, user_id) VALUES (?1, ?2);",
+                (service_account_id, user_id),
+            )
+            .expect("Failed to write service account to database!");
+
+        tracing::info!("Added service account {service_account_id} to account {user_id}");
+
+        Some(service_account_id)
+    }
+
+    /// Renames one of a user's service accounts. The name can't be shared with any of their other service accounts.
+    pub fn rename_service_account(
+        &self,
+        user_id: u32,
+        service_account_id: u32,
+        name: &str,
+    ) -> Result<(), ServiceAccountNameError> {
+        if name.is_empty() {
+            return Err(ServiceAccountNameError::Empty);
+        }
+        if name.len() > SERVICE_ACCOUNT_NAME_MAX_LENGTH {
+            return Err(ServiceAccountNameError::TooLong);
+        }
+
+        // check and write under the same lock, so two renames can't both take the same name
+        let connection = self.connection.lock().unwrap();
+
+        let taken = Self::service_accounts(&connection, user_id)
+            .iter()
+            .any(|account| {
+                account.id != service_account_id && account.name.eq_ignore_ascii_case(name)
+            });
+        if taken {
+            return Err(ServiceAccountNameError::Taken);
         }
+
+        connection
+            .execute(
+                "UPDATE service_accounts SET name = ?1 WHERE id = ?2 AND user_id = ?3;",
+                (name, service_account_id, user_id),
+            )
+            .unwrap();
+
+        Ok(())
+    }
+
+    /// Removes one of a user's service accounts. Their characters live on the world server, and have to be deleted separately.
+    pub fn remove_service_account(&self, user_id: u32, service_account_id: u32) {
+        let connection = self.connection.lock().unwrap();
+
+        connection
+            .execute(
+                "DELETE FROM service_accounts WHERE id = ?1 AND user_id = ?2;",
+                (service_account_id, user_id),
+            )
+            .unwrap();
+
+        tracing::info!("Removed service account {service_account_id} from account {user_id}");
     }
 
     /// Login as user, returns a session id.
@@ -100,80 +185,152 @@ impl LoginDatabase {
             selected_row = stmt.query_row((username,), |row| Ok((row.get(0)?, row.get(1)?)));
         }
 
-        if let Ok((_id, their_password)) = selected_row {
-            if their_password == password {
-                return self.create_session(_id).ok_or(LoginError::InternalError);
-            } else {
+        if let Ok((id, their_password)) = selected_row {
+            if !self.verify_password(id, &their_password, password) {
                 return Err(LoginError::WrongPassword);
             }
+
+            return self.create_session(id).ok_or(LoginError::InternalError);
         }
 
         Err(LoginError::WrongUsername)
     }
 
+    /// Checks if `password` is the current password of a user, e.g. before letting them change it.
+    pub fn check_password(&self, user_id: u32, password: &str) -> bool {
+        let their_password: String;
+        {
+            let connection = self.connection.lock().unwrap();
+
+            let mut stmt = connection
+                .prepare("SELECT password FROM users WHERE id = ?1")
+                .unwrap();
+            let Ok(found_password) = stmt.query_row((user_id,), |row| row.get(0)) else {
+                return false;
+            };
+            their_password = found_password;
+        }
+
+        self.verify_password(user_id, &their_password, password)
+    }
+
+    /// Compares `password` against what's stored in the database for this user.
+    fn verify_password(&self, user_id: u32, their_password: &str, password: &str) -> bool {
+        match PasswordHash::new(their_password) {
+            Ok(hash) => Argon2::default()
+                .verify_password(password.as_bytes(), &hash)
+                .is_ok(),
+            // accounts created before we hashed passwords still have them in plain text
+            Err(_) => {
+                if !bool::from(their_password.as_bytes().ct_eq(password.as_bytes())) {
+                    return false;
+                }
+
+                tracing::info!("Rehashing the plain text password of account {user_id}");
+                self.write_password(user_id, password);
+
+                true
+            }
+        }
+    }
+
+    /// Hashes a password with a new salt. The result includes the salt and parameters, so it's all that's needed to verify it later.
+    fn hash_password(password: &str) -> String {
+        let salt = SaltString::generate(&mut OsRng);
+
+        Argon2::default()
+            .hash_password(password.as_bytes(), &salt)
+            .expect("Failed to hash password!")
+            .to_string()
+    }
+
+    fn write_password(&self, user_id: u32, password: &str) {
+        let connection = self.connection.lock().unwrap();
+
+        connection
+            .execute(
+                "UPDATE users SET password = ?1 WHERE id = ?2;",
+                (Self::hash_password(password), user_id),
+            )
+            .expect("Failed to write password to database!");
+    }
+
+    /// Changes the password of a user, and logs them out everywhere.
+    pub fn change_password(&self, user_id: u32, password: &str) {
+        self.write_password(user_id, password);
+        self.revoke_all_sessions(user_id);
+    }
+
+    /// Generates a new SID, which has to come from a cryptographically secure source since it's as good as a password.
     fn generate_sid() -> String {
-        let random_id: String =
-            String::from_utf8((0..56).map(|_| fastrand::alphanumeric() as u8).collect())
-                .expect("Failed to create random SID");
-        random_id.to_lowercase()
+        let mut bytes = [0u8; 28];
+        OsRng.fill_bytes(&mut bytes);
+
+        bytes.iter().map(|byte| format!("{byte:02x}")).collect()
     }
 
-    /// Create a new session for user, which replaces the last one (if any)
+    /// Create a new session for user. If they have too many, their oldest ones are ended.
     pub fn create_session(&self, user_id: u32) -> Option<String> {
+        let config = get_config();
         let connection = self.connection.lock().unwrap();
 
         let sid = Self::generate_sid();
+        let now = timestamp_secs();
+
+        // clean up any expired sessions while we're here
+        connection
+            .execute("DELETE FROM sessions WHERE expires <= ?1;", (now,))
+            .ok()?;
 
         connection
             .execute(
-                "INSERT OR REPLACE INTO sessions VALUES (?1, ?2);",
-                (user_id, &sid),
+                "INSERT INTO sessions VALUES (?1, ?2, ?3, ?4);",
+                (&sid, user_id, now, now + config.login.session_lifetime),
             )
             .ok()?;
 
+        // zero means there's no limit
+        if config.login.max_sessions > 0 {
+            connection
+                .execute(
+                    "DELETE FROM sessions WHERE user_id = ?1 AND sid NOT IN (SELECT sid FROM sessions WHERE user_id = ?1 ORDER BY created DESC, rowid DESC LIMIT ?2);",
+                    (user_id, config.login.max_sessions),
+                )
+                .ok()?;
+        }
+
         tracing::info!("Created new session for account {user_id}: {sid}");
 
         Some(sid)
     }
 
-    /// Gets the service account list
-    pub fn check_session(&self, sid: &str) -> Vec<ServiceAccount> {
+    /// Ends a session, e.g. when logging out.
+    pub fn revoke_session(&self, sid: &str) {
         let connection = self.connection.lock().unwrap();
 
-        // get user id
-        let user_id: u32;
-        {
-            let mut stmt = connection
-                .prepare("SELECT user_id FROM sessions WHERE sid = ?1")
-                .ok()
-                .unwrap();
-            if let Ok(found_user_id) = stmt.query_row((sid,), |row| row.get(0)) {
-                user_id = found_user_id;
-            } else {
-                return Vec::default();
-            }
-        }
+        connection
+            .execute("DELETE FROM sessions WHERE sid = ?1;", (sid,))
+            .unwrap();
+    }
 
-        // service accounts
-        {
-            let mut stmt = connection
-                .prepare("SELECT id FROM service_accounts WHERE user_id = ?1")
-                .ok()
-                .unwrap();
-            let accounts = stmt.query_map((user_id,), |row| row.get(0)).unwrap();
-
-            let mut service_accounts = Vec::new();
-            for (index, id) in accounts.enumerate() {
-                service_accounts.push(ServiceAccount {
-                    id: id.unwrap(),
-                    unk1: 0,
-                    index: index as u32,
-                    name: format!("FINAL FANTASY XIV {}", index + 1), // TODO: don't add the "1" if you only have one service account
-                });
-            }
+    /// Ends every session a user has.
+    pub fn revoke_all_sessions(&self, user_id: u32) {
+        let connection = self.connection.lock().unwrap();
 
-            service_accounts
-        }
+        connection
+            .execute("DELETE FROM sessions WHERE user_id = ?1;", (user_id,))
+            .unwrap();
+
+        tracing::info!("Revoked all sessions for account {user_id}");
+    }
+
+    /// Gets the service account list
+    pub fn check_session(&self, sid: &str) -> Vec<ServiceAccount> {
+        let Some(user_id) = self.get_user_id(sid) else {
+            return Vec::default();
+        };
+
+        self.get_service_accounts(user_id)
     }
 
     /// Checks if a username is taken
@@ -190,14 +347,16 @@ impl LoginDatabase {
         selected_row.is_ok()
     }
 
-    pub fn get_user_id(&self, sid: &str) -> u32 {
+    /// Finds the user a session belongs to, or returns None if it doesn't exist or has expired.
+    pub fn get_user_id(&self, sid: &str) -> Option<u32> {
         let connection = self.connection.lock().unwrap();
 
         let mut stmt = connection
-            .prepare("SELECT user_id FROM sessions WHERE sid = ?1")
+            .prepare("SELECT user_id FROM sessions WHERE sid = ?1 AND expires > ?2")
             .ok()
             .unwrap();
-        stmt.query_row((sid,), |row| row.get(0)).unwrap()
+        stmt.query_row((sid, timestamp_secs()), |row| row.get(0))
+            .ok()
     }
 
     pub fn get_username(&self, user_id: u32) -> String {
@@ -210,14 +369,70 @@ impl LoginDatabase {
         stmt.query_row((user_id,), |row| row.get(0)).unwrap()
     }
 
-    /// TODO: only works for one
-    pub fn get_service_account(&self, user_id: u32) -> u32 {
+    /// Returns every service account belonging to a user, in the order they were created.
+    pub fn get_service_accounts(&self, user_id: u32) -> Vec<ServiceAccount> {
         let connection = self.connection.lock().unwrap();
 
+        Self::service_accounts(&connection, user_id)
+    }
+
+    /// Same as `get_service_accounts`, for when the connection is already locked.
+    fn service_accounts(connection: &Connection, user_id: u32) -> Vec<ServiceAccount> {
         let mut stmt = connection
-            .prepare("SELECT id FROM service_accounts WHERE user_id = ?1")
-            .ok()
+            .prepare("SELECT id, name FROM service_accounts WHERE user_id = ?1 ORDER BY rowid")
             .unwrap();
-        stmt.query_row((user_id,), |row| row.get(0)).unwrap()
+        let accounts: Vec<(u32, Option<String>)> = stmt
+            .query_map((user_id,), |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .map(|x| x.unwrap())
+            .collect();
+
+        let num_accounts = accounts.len();
+        accounts
+            .into_iter()
+            .enumerate()
+            .map(|(index, (id, name))| ServiceAccount {
+                id,
+                unk1: 0,
+                index: index as u32,
+                name: name.unwrap_or_else(|| {
+                    if num_accounts == 1 {
+                        "FINAL FANTASY XIV".to_string()
+                    } else {
+                        format!("FINAL FANTASY XIV {}", index + 1)
+                    }
+                }),
+            })
+            .collect()
+    }
+
+    /// Checks if a service account belongs to a user.
+    pub fn owns_service_account(&self, user_id: u32, service_account_id: u32) -> bool {
+        let connection = self.connection.lock().unwrap();
+
+        let mut stmt = connection
+            .prepare("SELECT id FROM service_accounts WHERE id = ?1 AND user_id = ?2")
+            .unwrap();
+        stmt.exists((service_account_id, user_id)).unwrap()
+    }
+
+    /// Deletes a user, along with their service accounts and sessions. Their characters live on the world server, and have to be deleted separately.
+    pub fn delete_user(&self, user_id: u32) {
+        let connection = self.connection.lock().unwrap();
+
+        connection
+            .execute("DELETE FROM sessions WHERE user_id = ?1;", (user_id,))
+            .unwrap();
+        connection
+            .execute(
+                "DELETE FROM service_accounts WHERE user_id = ?1;",
+                (user_id,),
+            )
+            .unwrap();
+        connection
+            .execute("DELETE FROM users WHERE id = ?1;", (user_id,))
+            .unwrap();
+
+        tracing::info!("Deleted account {user_id}");
     }
 }
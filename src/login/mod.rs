@@ -1,2 +1,4 @@
 mod database;
-pub use database::{LoginDatabase, LoginError};
+pub use database::{
+    LoginDatabase, LoginError, SERVICE_ACCOUNT_NAME_MAX_LENGTH, ServiceAccountNameError,
+};
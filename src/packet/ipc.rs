@@ -53,7 +53,7 @@ where
     #[brw(pad_before = 2)] // empty
     /// Unknown purpose, but safe to keep 0.
     pub option: u16,
-    /// The timestamp of this packet in seconds since UNIX epoch.
+    /// The timestamp of this packet in seconds since UNIX epoch. Custom IPC uses this for a request id instead.
     pub timestamp: u32,
     /// The data associated with the opcode.
     #[brw(pad_before = 4)]
@@ -21,3 +21,9 @@ pub mod oodle;
 mod send_helpers;
 #[cfg(not(target_family = "wasm"))]
 pub use send_helpers::{send_keep_alive, send_packet};
+
+/// Receive packet helpers.
+#[cfg(not(target_family = "wasm"))]
+mod receive_helpers;
+#[cfg(not(target_family = "wasm"))]
+pub use receive_helpers::read_packet;
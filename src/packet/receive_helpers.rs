@@ -0,0 +1,32 @@
+use std::io::{Cursor, Error, ErrorKind};
+
+use binrw::BinRead;
+use tokio::{io::AsyncReadExt, net::TcpStream};
+
+use crate::RECEIVE_BUFFER_SIZE;
+
+use super::PacketHeader;
+
+/// Reads exactly one packet, waiting for the rest of it if it's split across several reads.
+pub async fn read_packet(socket: &mut TcpStream) -> Result<Vec<u8>, Error> {
+    let header_size = std::mem::size_of::<PacketHeader>();
+
+    let mut buf = vec![0; header_size];
+    socket.read_exact(&mut buf).await?;
+
+    let header = PacketHeader::read_le(&mut Cursor::new(&buf))
+        .map_err(|err| Error::new(ErrorKind::InvalidData, err.to_string()))?;
+
+    let size = header.size as usize;
+    if size < header_size || size > RECEIVE_BUFFER_SIZE {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("Invalid packet size {size}"),
+        ));
+    }
+
+    buf.resize(size, 0);
+    socket.read_exact(&mut buf[header_size..]).await?;
+
+    Ok(buf)
+}
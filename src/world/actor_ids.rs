@@ -0,0 +1,188 @@
+use std::{
+    collections::{HashSet, VecDeque},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Range of actor ids the client expects players to be in.
+const PLAYER_ID_RANGE: (u32, u32) = (0x10000000, 0x20000000);
+/// Range of actor ids the client expects battle NPCs (and other server-spawned actors) to be in.
+const NPC_ID_RANGE: (u32, u32) = (0x40000000, 0x50000000);
+
+/// How long a released id sits unused before it's handed out again, so packets still in flight for the old actor can't be mistaken for the new one.
+const RECYCLE_DELAY: Duration = Duration::from_secs(60);
+
+#[derive(Debug)]
+struct IdRange {
+    /// The next never-used id.
+    next: u32,
+    /// One past the last id in this range.
+    end: u32,
+    /// Ids currently belonging to an actor.
+    in_use: HashSet<u32>,
+    /// Ids that were released, and when.
+    released: VecDeque<(u32, Instant)>,
+}
+
+impl IdRange {
+    fn new((start, end): (u32, u32)) -> Self {
+        Self {
+            next: start,
+            end,
+            in_use: HashSet::new(),
+            released: VecDeque::new(),
+        }
+    }
+
+    fn allocate(&mut self) -> u32 {
+        // prefer recycling ids that have been released for long enough
+        let recyclable = self
+            .released
+            .front()
+            .is_some_and(|(_, released_at)| released_at.elapsed() >= RECYCLE_DELAY);
+
+        let id = if recyclable {
+            self.released.pop_front().unwrap().0
+        } else {
+            // skip any ids that were reserved out of order
+            while self.next < self.end && self.in_use.contains(&self.next) {
+                self.next += 1;
+            }
+
+            if self.next < self.end {
+                let id = self.next;
+                self.next += 1;
+                id
+            } else if let Some((id, _)) = self.released.pop_front() {
+                tracing::warn!("Ran out of fresh actor ids, recycling {id:#X} early!");
+                id
+            } else {
+                panic!("Ran out of actor ids!");
+            }
+        };
+
+        self.in_use.insert(id);
+
+        id
+    }
+
+    fn reserve(&mut self, id: u32) {
+        self.in_use.insert(id);
+        self.released.retain(|(released_id, _)| *released_id != id);
+    }
+
+    fn release(&mut self, id: u32) {
+        if self.in_use.remove(&id) {
+            self.released.push_back((id, Instant::now()));
+        }
+    }
+}
+
+/// Hands out actor ids that are unique across the whole world server, shared between the database (for players) and the server main loop (for NPCs).
+#[derive(Debug)]
+pub struct ActorIdAllocator {
+    players: Mutex<IdRange>,
+    npcs: Mutex<IdRange>,
+}
+
+impl Default for ActorIdAllocator {
+    fn default() -> Self {
+        Self {
+            players: Mutex::new(IdRange::new(PLAYER_ID_RANGE)),
+            npcs: Mutex::new(IdRange::new(NPC_ID_RANGE)),
+        }
+    }
+}
+
+impl ActorIdAllocator {
+    /// Allocates a new id for a player character.
+    pub fn allocate_player(&self) -> u32 {
+        self.players.lock().unwrap().allocate()
+    }
+
+    /// Allocates a new id for a server-spawned actor, like a battle NPC.
+    pub fn allocate_npc(&self) -> u32 {
+        self.npcs.lock().unwrap().allocate()
+    }
+
+    /// Marks an id as taken, e.g. one of the player ids already stored in the database.
+    pub fn reserve(&self, id: u32) {
+        self.range_for(id).lock().unwrap().reserve(id);
+    }
+
+    /// Gives an id back, so it can be reused once it's safe to.
+    pub fn release(&self, id: u32) {
+        self.range_for(id).lock().unwrap().release(id);
+    }
+
+    fn range_for(&self, id: u32) -> &Mutex<IdRange> {
+        if (PLAYER_ID_RANGE.0..PLAYER_ID_RANGE.1).contains(&id) {
+            &self.players
+        } else {
+            &self.npcs
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allocates_from_separate_ranges() {
+        let allocator = ActorIdAllocator::default();
+
+        assert_eq!(allocator.allocate_player(), PLAYER_ID_RANGE.0);
+        assert_eq!(allocator.allocate_player(), PLAYER_ID_RANGE.0 + 1);
+        assert_eq!(allocator.allocate_npc(), NPC_ID_RANGE.0);
+        assert_eq!(allocator.allocate_npc(), NPC_ID_RANGE.0 + 1);
+    }
+
+    #[test]
+    fn released_ids_wait_before_recycling() {
+        let mut range = IdRange::new(NPC_ID_RANGE);
+
+        let first = range.allocate();
+        range.release(first);
+
+        // too soon, so a fresh id is handed out instead
+        let second = range.allocate();
+        assert_ne!(second, first);
+
+        // pretend the delay has passed
+        range.released[0].1 = Instant::now() - RECYCLE_DELAY;
+        assert_eq!(range.allocate(), first);
+    }
+
+    #[test]
+    fn releasing_unused_ids_does_nothing() {
+        let mut range = IdRange::new(NPC_ID_RANGE);
+
+        range.release(NPC_ID_RANGE.0);
+        assert!(range.released.is_empty());
+    }
+
+    #[test]
+    fn reserved_ids_are_skipped() {
+        let allocator = ActorIdAllocator::default();
+
+        // e.g. players already in the database
+        allocator.reserve(PLAYER_ID_RANGE.0);
+        allocator.reserve(PLAYER_ID_RANGE.0 + 2);
+
+        assert_eq!(allocator.allocate_player(), PLAYER_ID_RANGE.0 + 1);
+        assert_eq!(allocator.allocate_player(), PLAYER_ID_RANGE.0 + 3);
+    }
+
+    #[test]
+    fn reserving_cancels_a_pending_recycle() {
+        let mut range = IdRange::new(PLAYER_ID_RANGE);
+
+        let id = range.allocate();
+        range.release(id);
+        range.reserve(id);
+
+        assert!(range.released.is_empty());
+        assert_ne!(range.allocate(), id);
+    }
+}
@@ -0,0 +1,248 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use mlua::{Function, Lua, LuaSerdeExt, Value};
+use serde::Deserialize;
+
+use crate::{
+    common::{ObjectId, Position},
+    config::get_config,
+    ipc::zone::{ActionEffect, DamageElement, DamageKind, DamageType, EffectKind},
+};
+
+use super::EffectsBuilder;
+
+/// How a battle NPC behaves in combat. Scripts can change these per BNpcBase by defining `getBehaviour`.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default)]
+pub struct BattleNpcBehaviour {
+    /// How close (in yalms) a player has to get before it attacks them on its own. Set to 0 to only fight back when hit.
+    pub aggro_range: f32,
+    /// How far (in yalms) it can be pulled from where it spawned before giving up and walking back.
+    pub leash_range: f32,
+    /// How close (in yalms) it has to be to its target to auto-attack them.
+    pub attack_range: f32,
+    /// How fast it moves, in yalms per second.
+    pub speed: f32,
+    /// Seconds between auto-attacks.
+    pub auto_attack_interval: f32,
+    /// How much damage each auto-attack does, unless the script defines `doAutoAttack`.
+    pub auto_attack_damage: u16,
+}
+
+impl Default for BattleNpcBehaviour {
+    fn default() -> Self {
+        Self {
+            aggro_range: 8.0,
+            leash_range: 40.0,
+            attack_range: 3.0,
+            speed: 6.0,
+            auto_attack_interval: 3.0,
+            auto_attack_damage: 5,
+        }
+    }
+}
+
+/// What a battle NPC is doing right now.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BattleNpcState {
+    /// Standing around, waiting for someone to come close.
+    #[default]
+    Idle,
+    /// Fighting whoever is at the top of its hate list.
+    Engaged,
+    /// Was pulled too far away, and is walking back to where it spawned.
+    Leashing,
+}
+
+/// The server-side state of a battle NPC, which decides who it attacks and where it moves.
+#[derive(Debug, Clone)]
+pub struct BattleNpc {
+    pub behaviour: BattleNpcBehaviour,
+    pub state: BattleNpcState,
+    /// Where it spawned, and goes back to when leashing.
+    pub home_position: Position,
+    pub home_rotation: f32,
+    /// How much it wants to attack each actor.
+    pub hate: HashMap<ObjectId, u32>,
+    /// When it's allowed to auto-attack again.
+    pub next_auto_attack: Instant,
+    /// The script registered for its BNpcBase, if there is one.
+    pub script: Option<String>,
+}
+
+impl BattleNpc {
+    pub fn new(home_position: Position, home_rotation: f32, script: Option<String>) -> Self {
+        Self {
+            behaviour: BattleNpcBehaviour::default(),
+            state: BattleNpcState::Idle,
+            home_position,
+            home_rotation,
+            hate: HashMap::new(),
+            next_auto_attack: Instant::now(),
+            script,
+        }
+    }
+
+    /// Makes it want to attack `actor_id` more. Ignored while it's walking back to where it spawned.
+    pub fn add_hate(&mut self, actor_id: ObjectId, amount: u32) {
+        if self.state == BattleNpcState::Leashing {
+            return;
+        }
+
+        *self.hate.entry(actor_id).or_default() += amount;
+        self.state = BattleNpcState::Engaged;
+    }
+
+    /// Who it wants to attack the most, if anyone.
+    pub fn top_hate(&self) -> Option<ObjectId> {
+        self.hate
+            .iter()
+            .max_by_key(|(_, hate)| **hate)
+            .map(|(actor_id, _)| *actor_id)
+    }
+
+    /// Forgets about everyone, and starts walking back to where it spawned.
+    pub fn leash(&mut self) {
+        self.hate.clear();
+        self.state = BattleNpcState::Leashing;
+    }
+
+    /// How long to wait between auto-attacks.
+    pub fn auto_attack_interval(&self) -> Duration {
+        Duration::from_secs_f32(self.behaviour.auto_attack_interval.max(0.0))
+    }
+}
+
+/// Moves from `from` towards `to` by at most `distance` yalms, stopping `stop_short` yalms before reaching it.
+/// Returns the new position, and the rotation needed to face `to`.
+pub fn step_towards(
+    from: Position,
+    to: Position,
+    distance: f32,
+    stop_short: f32,
+) -> (Position, f32) {
+    let x = to.x - from.x;
+    let z = to.z - from.z;
+    let rotation = x.atan2(z);
+
+    let remaining = from.distance(&to) - stop_short;
+    if remaining <= 0.0 {
+        return (from, rotation);
+    }
+
+    let t = distance.min(remaining) / from.distance(&to);
+    (
+        Position {
+            x: from.x + x * t,
+            y: from.y + (to.y - from.y) * t,
+            z: from.z + z * t,
+        },
+        rotation,
+    )
+}
+
+/// A rough guess at a battle NPC's max HP from its level, until we know how retail calculates it.
+pub fn estimate_battle_npc_hp(level: u8) -> u32 {
+    let level = level.max(1) as u32;
+    50 + level * 20 + level * level * 3
+}
+
+/// The auto-attack used when a script doesn't define `doAutoAttack`.
+pub fn default_auto_attack(amount: u16) -> EffectsBuilder {
+    EffectsBuilder {
+        effects: vec![ActionEffect {
+            kind: EffectKind::Damage {
+                damage_kind: DamageKind::Normal,
+                damage_type: DamageType::Physical,
+                damage_element: DamageElement::Unaspected,
+                bonus_percent: 0,
+                unk3: 0,
+                unk4: 0,
+                amount,
+            },
+        }],
+        ..Default::default()
+    }
+}
+
+/// A battle NPC script that has already been run, so it doesn't have to be loaded again for every NPC or auto-attack.
+#[derive(Debug, Clone)]
+pub struct BattleNpcScript {
+    /// What the script's `getBehaviour` returned, or the defaults if it doesn't define it.
+    pub behaviour: BattleNpcBehaviour,
+    /// The script's `doAutoAttack`, if it defines one.
+    pub auto_attack: Option<Function>,
+}
+
+impl BattleNpcScript {
+    /// Runs the script and grabs its functions. Returns None if it failed to load.
+    pub fn load(lua: &Lua, script: &str) -> Option<Self> {
+        let config = get_config();
+        let file_name = format!("{}/{}", &config.world.scripts_location, script);
+
+        let Ok(contents) = std::fs::read(&file_name) else {
+            tracing::warn!("Failed to read battle NPC script {file_name}!");
+            return None;
+        };
+
+        // each script gets its own environment, so they can't overwrite each other's functions
+        let environment = lua.create_table().ok()?;
+        let metatable = lua.create_table().ok()?;
+        metatable.set("__index", lua.globals()).ok()?;
+        environment.set_metatable(Some(metatable));
+
+        if let Err(err) = lua
+            .load(contents)
+            .set_name("@".to_string() + &file_name)
+            .set_environment(environment.clone())
+            .exec()
+        {
+            tracing::warn!("Failed to run battle NPC script {file_name}: {err}");
+            return None;
+        }
+
+        let behaviour = match environment.get::<Option<Function>>("getBehaviour") {
+            Ok(Some(func)) => match func
+                .call::<Value>(())
+                .and_then(|value| lua.from_value(value))
+            {
+                Ok(behaviour) => behaviour,
+                Err(err) => {
+                    tracing::warn!("getBehaviour in {script} failed: {err}");
+                    BattleNpcBehaviour::default()
+                }
+            },
+            _ => BattleNpcBehaviour::default(),
+        };
+
+        Some(Self {
+            behaviour,
+            auto_attack: environment
+                .get::<Option<Function>>("doAutoAttack")
+                .ok()
+                .flatten(),
+        })
+    }
+
+    /// Asks the script what an auto-attack does, or returns None if it doesn't say.
+    pub fn run_auto_attack(&self, script: &str) -> Option<EffectsBuilder> {
+        let func = self.auto_attack.as_ref()?;
+
+        match func.call::<Value>(()) {
+            Ok(Value::UserData(effects)) => {
+                effects.borrow::<EffectsBuilder>().ok().map(|x| x.clone())
+            }
+            Ok(_) => {
+                tracing::warn!("doAutoAttack in {script} didn't return an EffectsBuilder!");
+                None
+            }
+            Err(err) => {
+                tracing::warn!("doAutoAttack in {script} failed: {err}");
+                None
+            }
+        }
+    }
+}
@@ -0,0 +1,121 @@
+use std::{net::SocketAddr, sync::Arc, time::Instant};
+
+use tokio::net::TcpStream;
+
+use crate::{
+    common::timestamp_secs,
+    config::WorldConfig,
+    ipc::chat::{ClientChatIpcSegment, ServerChatIpcData, ServerChatIpcSegment},
+    opcodes::ServerChatIpcType,
+    packet::{
+        CompressionType, ConnectionType, PacketSegment, PacketState, SegmentData, SegmentType,
+        parse_packet, send_packet,
+    },
+};
+
+use super::{
+    WorldDatabase,
+    common::{ClientId, ServerHandle},
+};
+
+/// Represents a single chat connection, which the client opens alongside their zone connection.
+pub struct ChatConnection {
+    pub config: WorldConfig,
+    pub socket: TcpStream,
+
+    pub state: PacketState,
+
+    /// The actor id of the player this connection belongs to, which is also what links it to their zone connection.
+    pub actor_id: u32,
+    pub content_id: u64,
+    pub name: String,
+
+    pub ip: SocketAddr,
+    pub id: ClientId,
+    pub handle: ServerHandle,
+
+    pub database: Arc<WorldDatabase>,
+
+    pub last_keep_alive: Instant,
+}
+
+impl ChatConnection {
+    pub fn parse_packet(
+        &mut self,
+        data: &[u8],
+    ) -> (Vec<PacketSegment<ClientChatIpcSegment>>, ConnectionType) {
+        parse_packet(data, &mut self.state)
+    }
+
+    pub async fn send_segment(&mut self, segment: PacketSegment<ServerChatIpcSegment>) {
+        send_packet(
+            &mut self.socket,
+            &mut self.state,
+            ConnectionType::Chat,
+            if self.config.enable_packet_compression {
+                CompressionType::Oodle
+            } else {
+                CompressionType::Uncompressed
+            },
+            &[segment],
+        )
+        .await;
+    }
+
+    pub async fn send_ipc_self(&mut self, ipc: ServerChatIpcSegment) {
+        self.send_segment(PacketSegment {
+            source_actor: self.actor_id,
+            target_actor: self.actor_id,
+            segment_type: SegmentType::Ipc,
+            data: SegmentData::Ipc { data: ipc },
+        })
+        .await;
+    }
+
+    pub async fn initialize(&mut self, actor_id: u32) {
+        self.actor_id = actor_id;
+
+        let content_id = self.database.find_player_data(actor_id).content_id;
+        self.content_id = content_id;
+        self.name = self.database.find_chara_make(content_id).name;
+
+        tracing::info!("Client {actor_id} is initializing chat session...");
+
+        // We have send THEM a keep alive
+        self.send_segment(PacketSegment {
+            segment_type: SegmentType::KeepAliveRequest,
+            data: SegmentData::KeepAliveRequest {
+                id: 0xE0037603u32,
+                timestamp: timestamp_secs(),
+            },
+            ..Default::default()
+        })
+        .await;
+
+        // initialize connection
+        self.send_segment(PacketSegment {
+            segment_type: SegmentType::Initialize,
+            data: SegmentData::Initialize {
+                player_id: self.actor_id,
+                timestamp: timestamp_secs(),
+            },
+            ..Default::default()
+        })
+        .await;
+
+        // send login reply
+        {
+            let ipc = ServerChatIpcSegment {
+                op_code: ServerChatIpcType::LoginReply,
+                timestamp: timestamp_secs(),
+                data: ServerChatIpcData::LoginReply {
+                    timestamp: 0,
+                    sid: 0,
+                },
+                ..Default::default()
+            };
+
+            self.send_ipc_self(ipc).await;
+        }
+    }
+}
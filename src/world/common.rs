@@ -6,23 +6,37 @@ use std::{
     },
 };
 
-use tokio::sync::mpsc::Sender;
+use tokio::sync::{mpsc::Sender, oneshot};
 
 use crate::{
     common::Position,
-    ipc::zone::{
-        ActionRequest, ActorControl, ActorControlSelf, ActorControlTarget, ClientTrigger,
-        CommonSpawn, Config, NpcSpawn,
+    ipc::{
+        chat::ChatChannel,
+        zone::{
+            ActionRequest, ActionResult, ActorControl, ActorControlSelf, ActorControlTarget,
+            ClientTrigger, CommonSpawn, Config, GameMasterRank, NpcSpawn,
+        },
     },
 };
 
-use super::Actor;
+use super::{Actor, EffectsBuilder, RconCommand};
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
 pub struct ClientId(usize);
 
+/// Which copy of a zone a connection wants to be moved into.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum InstanceRequest {
+    /// The copy shared with everyone else, e.g. the overworld.
+    #[default]
+    Shared,
+    /// A fresh copy that only this connection is in, e.g. inn rooms and opening areas.
+    Private,
+}
+
+#[derive(Clone)]
 pub enum FromServer {
-    /// A chat message.
+    /// A message shown in the chat log, either from the server itself or someone talking in a channel.
     Message(String),
     /// An actor has been spawned.
     ActorSpawn(Actor, NpcSpawn),
@@ -40,10 +54,32 @@ pub enum FromServer {
     ActionComplete(ActionRequest),
     /// Action has been cancelled
     ActionCancelled(),
+    /// An actor nearby used an action, given as their actor id and the result.
+    ActionResult(u32, ActionResult),
+    /// An actor's HP or MP changed, given as their actor id, HP and MP.
+    UpdateHpMp(u32, u32, u16),
+    /// An action gave us a status effect, given as the effect id, how long it lasts and who gave it to us.
+    GainEffect(u16, f32, u32),
+    /// Time has passed, and our status effects should count down by this many seconds.
+    TickStatusEffects(f32),
+    /// The weather changed in our instance.
+    ChangeWeather(u16),
     /// Update an actor's equip display flags.
     UpdateConfig(u32, Config),
     /// Update an actor's model IDs.
     ActorEquip(u32, u64, [u32; 10]),
+    /// The server moved us to another zone, and we should begin loading it.
+    ChangeZone(u16),
+    /// An administrator wants us moved to another zone.
+    Teleport(u16),
+    /// An administrator changed our GM rank.
+    SetGmRank(GameMasterRank),
+    /// We should write the player back to the database.
+    Save,
+    /// An administrator kicked us, and we should disconnect.
+    Kick,
+    /// The server is shutting down, and we should save and disconnect.
+    Shutdown,
 }
 
 #[derive(Debug, Clone)]
@@ -51,6 +87,7 @@ pub struct ClientHandle {
     pub id: ClientId,
     pub ip: SocketAddr,
     pub channel: Sender<FromServer>,
+    /// The player's actor id. A player's zone and chat connections share this, and it's how the server links them together.
     pub actor_id: u32,
 }
 
@@ -77,21 +114,26 @@ impl ClientHandle {
 }
 
 pub enum ToServer {
-    /// A new connection has started.
-    NewClient(ClientHandle),
-    /// The connection sent a message.
-    Message(ClientId, String),
+    /// A new connection has started, along with the player's name.
+    NewClient(ClientHandle, String),
+    /// A new chat connection has started.
+    NewChatClient(ClientHandle),
+    /// The connection's player sent a message in a channel.
+    Message(ClientId, u32, ChatChannel, String),
     /// The connection's player moved.
     ActorMoved(ClientId, u32, Position, f32),
     /// The connection has recieved a client trigger.
     ClientTrigger(ClientId, u32, ClientTrigger),
-    /// The connection loaded into a zone.
-    // TODO: the connection should not be in charge and telling the global server what zone they just loaded in! but this will work for now
-    ZoneLoaded(ClientId, u16, CommonSpawn),
-    /// The connection left a zone.
-    LeftZone(ClientId, u32, u16),
+    /// The connection wants to move to another zone.
+    ChangeZone(ClientId, u32, u16, InstanceRequest),
+    /// The connection finished loading into the zone the server moved them to.
+    ZoneLoaded(ClientId, CommonSpawn),
     /// The connection disconnected.
     Disconnected(ClientId),
+    /// A command from the RCON console, and where to send the reply.
+    Rcon(RconCommand, oneshot::Sender<String>),
+    /// The server should disconnect everyone, and reply once they're all gone.
+    Shutdown(oneshot::Sender<()>),
     /// A fatal error occured.
     FatalError(std::io::Error),
     /// Spawn a friendly debug NPC.
@@ -102,6 +144,8 @@ pub enum ToServer {
     DebugSpawnClone(ClientId, u32),
     /// Request to perform an action
     ActionRequest(ClientId, u32, ActionRequest),
+    /// The connection ran the action's script, and its effects should be applied.
+    ActionExecuted(ClientId, u32, ActionRequest, EffectsBuilder),
     /// We want to update our own equip display flags.
     Config(ClientId, u32, Config),
     /// Tell the server what models IDs we have equipped.
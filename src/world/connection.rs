@@ -15,16 +15,12 @@ use crate::{
     },
     config::{WorldConfig, get_config},
     inventory::{ContainerType, Inventory, Item, Storage},
-    ipc::{
-        chat::ServerChatIpcSegment,
-        zone::{
-            ActionEffect, ActionRequest, ActionResult, ActorControl, ActorControlCategory,
-            ActorControlSelf, ActorControlTarget, ClientZoneIpcSegment, CommonSpawn, Config,
-            ContainerInfo, CurrencyInfo, DisplayFlag, EffectKind, Equip, GameMasterRank, InitZone,
-            ItemInfo, Move, NpcSpawn, ObjectKind, PlayerStats, PlayerSubKind, ServerZoneIpcData,
-            ServerZoneIpcSegment, StatusEffect, StatusEffectList, UpdateClassInfo, Warp,
-            WeatherChange,
-        },
+    ipc::zone::{
+        ActionRequest, ActionResult, ActorControl, ActorControlCategory, ActorControlSelf,
+        ActorControlTarget, ClientZoneIpcSegment, CommonSpawn, Config, ContainerInfo, CurrencyInfo,
+        DisplayFlag, Equip, GameMasterRank, InitZone, ItemInfo, Move, NpcSpawn, ObjectKind,
+        PlayerStats, PlayerSubKind, ServerZoneIpcData, ServerZoneIpcSegment, StatusEffect,
+        StatusEffectList, UpdateClassInfo, Warp, WeatherChange,
     },
     opcodes::ServerZoneIpcType,
     packet::{
@@ -36,9 +32,10 @@ use crate::{
 use super::{
     Actor, CharacterData, EffectsBuilder, Event, LuaPlayer, StatusEffects, ToServer, WorldDatabase,
     Zone,
-    common::{ClientId, ServerHandle},
+    common::{ClientId, InstanceRequest, ServerHandle},
     load_init_script,
     lua::Task,
+    zone::{INTENDED_USE_INN, INTENDED_USE_OPENING},
 };
 
 #[derive(Default)]
@@ -47,14 +44,15 @@ pub struct ExtraLuaState {
     pub event_scripts: HashMap<u32, String>,
     pub command_scripts: HashMap<String, String>,
     pub gm_command_scripts: HashMap<u32, String>,
+    pub battle_npc_scripts: HashMap<u32, String>,
 }
 
-#[derive(Debug, Default, Clone)]
+#[derive(Debug, Default, Clone, PartialEq)]
 pub struct TeleportQuery {
     pub aetheryte_id: u16,
 }
 
-#[derive(Debug, Default, Clone)]
+#[derive(Debug, Default, Clone, PartialEq)]
 pub struct PlayerData {
     // Static data
     pub actor_id: u32,
@@ -74,6 +72,8 @@ pub struct PlayerData {
     /// In radians.
     pub rotation: f32,
     pub zone_id: u16,
+    /// The aetheryte we return to after being knocked out.
+    pub homepoint: u32,
     pub inventory: Inventory,
 
     pub teleport_query: TeleportQuery,
@@ -92,6 +92,8 @@ pub struct ZoneConnection {
 
     pub state: PacketState,
     pub player_data: PlayerData,
+    /// What the player looked like the last time they were written to the database, to know if there's anything left to save.
+    pub saved_player_data: PlayerData,
 
     pub zone: Option<Zone>,
     pub spawn_index: u8,
@@ -144,21 +146,6 @@ impl ZoneConnection {
         .await;
     }
 
-    pub async fn send_chat_segment(&mut self, segment: PacketSegment<ServerChatIpcSegment>) {
-        send_packet(
-            &mut self.socket,
-            &mut self.state,
-            ConnectionType::Chat,
-            if self.config.enable_packet_compression {
-                CompressionType::Oodle
-            } else {
-                CompressionType::Uncompressed
-            },
-            &[segment],
-        )
-        .await;
-    }
-
     pub async fn initialize(&mut self, actor_id: u32) {
         // some still hardcoded values
         self.player_data.curr_hp = 100;
@@ -326,19 +313,40 @@ impl ZoneConnection {
         .await;
     }
 
+    /// Asks the server to move us to another zone. Inn rooms and opening areas get their own private instance, otherwise we share it with everyone else.
     pub async fn change_zone(&mut self, new_zone_id: u16) {
-        // tell everyone we're gone
-        // the connection already checks to see if the actor already exists, so it's seems harmless if we do
-        if let Some(zone) = &self.zone {
-            self.handle
-                .send(ToServer::LeftZone(
-                    self.id,
-                    self.player_data.actor_id,
-                    zone.id,
-                ))
-                .await;
+        let intended_use;
+        {
+            let mut game_data = self.gamedata.lock().unwrap();
+            intended_use = game_data.get_territory_intended_use(new_zone_id as u32);
         }
 
+        let request = match intended_use {
+            Some(INTENDED_USE_INN | INTENDED_USE_OPENING) => InstanceRequest::Private,
+            _ => InstanceRequest::Shared,
+        };
+
+        self.change_zone_instance(new_zone_id, request).await;
+    }
+
+    /// Asks the server to move us to a specific kind of instance of another zone. The zone is loaded once the server responds.
+    pub async fn change_zone_instance(&mut self, new_zone_id: u16, request: InstanceRequest) {
+        self.handle
+            .send(ToServer::ChangeZone(
+                self.id,
+                self.player_data.actor_id,
+                new_zone_id,
+                request,
+            ))
+            .await;
+    }
+
+    /// Loads into a zone the server has moved us to.
+    pub async fn load_zone(&mut self, new_zone_id: u16) {
+        // the client forgets about every actor when changing zones, so we should too
+        self.actors.clear();
+        self.spawn_index = 0;
+
         // load the new zone now
         {
             let mut game_data = self.gamedata.lock().unwrap();
@@ -452,6 +460,19 @@ impl ZoneConnection {
         self.change_zone(territory_type).await;
     }
 
+    /// Brings us back to life at our home point, after being knocked out.
+    pub async fn return_to_homepoint(&mut self) {
+        if self.player_data.curr_hp != 0 {
+            tracing::warn!("Tried to return to their home point while still alive, ignoring!");
+            return;
+        }
+
+        self.player_data.curr_hp = self.player_data.max_hp;
+        self.player_data.curr_mp = self.player_data.max_mp;
+
+        self.warp_aetheryte(self.player_data.homepoint).await;
+    }
+
     pub async fn change_weather(&mut self, new_weather_id: u16) {
         self.weather_id = new_weather_id;
 
@@ -663,7 +684,14 @@ impl ZoneConnection {
 
         for task in &player.queued_tasks {
             match task {
-                Task::ChangeTerritory { zone_id } => self.change_zone(*zone_id).await,
+                Task::ChangeTerritory { zone_id, private } => {
+                    if *private {
+                        self.change_zone_instance(*zone_id, InstanceRequest::Private)
+                            .await
+                    } else {
+                        self.change_zone(*zone_id).await
+                    }
+                }
                 Task::SetRemakeMode(remake_mode) => self
                     .database
                     .set_remake_mode(player.player_data.content_id, *remake_mode),
@@ -782,6 +810,9 @@ impl ZoneConnection {
                     self.player_data.completed_quests = vec![0xFF; COMPLETED_QUEST_BITMASK_SIZE];
                     self.send_quest_information().await;
                 }
+                Task::SetHomepoint { id } => {
+                    self.player_data.homepoint = *id;
+                }
             }
         }
         player.queued_tasks.clear();
@@ -838,11 +869,22 @@ impl ZoneConnection {
         }
     }
 
+    /// Writes the player back to the database, if anything changed since the last time.
+    pub fn save_player_data(&mut self) {
+        if self.player_data.actor_id == 0 || self.player_data == self.saved_player_data {
+            return;
+        }
+
+        self.database
+            .commit_player_data(&self.player_data, &self.saved_player_data);
+        self.saved_player_data = self.player_data.clone();
+    }
+
     pub async fn begin_log_out(&mut self) {
         self.gracefully_logged_out = true;
 
         // write the player back to the database
-        self.database.commit_player_data(&self.player_data);
+        self.save_player_data();
 
         // tell the client we're ready to disconnect at any moment'
         {
@@ -1102,64 +1144,60 @@ impl ZoneConnection {
             }
         }
 
-        // tell them the action results
+        // let the server apply the effects, and tell everyone nearby
         if let Some(effects_builder) = effects_builder {
-            let mut effects = [ActionEffect::default(); 8];
-            effects[..effects_builder.effects.len()].copy_from_slice(&effects_builder.effects);
-
-            if let Some(actor) = self.get_actor_mut(request.target.object_id) {
-                for effect in &effects_builder.effects {
-                    match effect.kind {
-                        EffectKind::Damage { amount, .. } => {
-                            actor.hp = actor.hp.saturating_sub(amount as u32);
-                        }
-                        _ => todo!(),
-                    }
-                }
-
-                let actor = *actor;
-                self.update_hp_mp(actor.id, actor.hp, 10000).await;
-            }
+            self.handle
+                .send(ToServer::ActionExecuted(
+                    self.id,
+                    self.player_data.actor_id,
+                    request,
+                    effects_builder,
+                ))
+                .await;
+        }
+    }
 
-            let ipc = ServerZoneIpcSegment {
-                op_code: ServerZoneIpcType::ActionResult,
-                timestamp: timestamp_secs(),
-                data: ServerZoneIpcData::ActionResult(ActionResult {
-                    main_target: request.target,
-                    target_id_again: request.target,
-                    action_id: request.action_key,
-                    animation_lock_time: 0.6,
-                    rotation: self.player_data.rotation,
-                    action_animation_id: request.action_key as u16, // assuming action id == animation id
-                    flag: 1,
-                    effect_count: effects_builder.effects.len() as u8,
-                    effects,
-                    unk1: 2662353,
-                    unk2: 3758096384,
-                    hidden_animation: 1,
-                    ..Default::default()
-                }),
-                ..Default::default()
-            };
+    /// Tells the client about an action someone used, which may have been us.
+    pub async fn send_action_result(&mut self, actor_id: u32, action_result: ActionResult) {
+        let ipc = ServerZoneIpcSegment {
+            op_code: ServerZoneIpcType::ActionResult,
+            timestamp: timestamp_secs(),
+            data: ServerZoneIpcData::ActionResult(action_result),
+            ..Default::default()
+        };
 
-            self.send_segment(PacketSegment {
-                source_actor: self.player_data.actor_id,
-                target_actor: self.player_data.actor_id,
-                segment_type: SegmentType::Ipc,
-                data: SegmentData::Ipc { data: ipc },
-            })
-            .await;
+        self.send_segment(PacketSegment {
+            source_actor: actor_id,
+            target_actor: self.player_data.actor_id,
+            segment_type: SegmentType::Ipc,
+            data: SegmentData::Ipc { data: ipc },
+        })
+        .await;
+    }
 
-            if let Some(actor) = self.get_actor(request.target.object_id) {
-                if actor.hp == 0 {
-                    tracing::info!("Despawning {} because they died!", actor.id.0);
-                    // if the actor died, despawn them
-                    /*connection.handle
-                     *                                       .send(ToServer::ActorDespawned(connection.id, actor.id.0))
-                     *                                       .await;*/
-                }
-            }
+    /// Keeps track of an actor's new HP and MP, and tells the client about it.
+    pub async fn set_hp_mp(&mut self, actor_id: u32, hp: u32, mp: u16) {
+        if actor_id == self.player_data.actor_id {
+            self.player_data.curr_hp = hp;
+            self.player_data.curr_mp = mp;
+        } else if let Some(actor) = self.get_actor_mut(ObjectId(actor_id)) {
+            actor.hp = hp;
         }
+
+        self.update_hp_mp(ObjectId(actor_id), hp, mp).await;
+    }
+
+    /// Gives us a status effect from an action.
+    pub async fn gain_effect(&mut self, effect_id: u16, duration: f32, source_actor_id: u32) {
+        self.status_effects
+            .add_from(effect_id, duration, source_actor_id);
+        self.process_effects_list().await;
+    }
+
+    /// Counts down our status effects, and tells the client about the ones that ran out.
+    pub async fn tick_status_effects(&mut self, elapsed: f32) {
+        self.status_effects.tick(elapsed);
+        self.process_effects_list().await;
     }
 
     pub async fn cancel_action(&mut self) {
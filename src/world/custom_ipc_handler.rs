@@ -3,18 +3,58 @@ use crate::{
         determine_initial_starting_zone,
         workdefinitions::{CharaMake, RemakeMode},
     },
-    config::get_config,
     inventory::Inventory,
     ipc::kawari::{CustomIpcData, CustomIpcSegment, CustomIpcType},
-    packet::{
-        CompressionType, ConnectionType, PacketSegment, SegmentData, SegmentType, send_packet,
-    },
 };
 
-use super::ZoneConnection;
+use subtle::ConstantTimeEq;
+
+use super::InternalConnection;
+
+/// Compares the secrets in constant time, so they can't be guessed by timing how long it takes to get rejected.
+fn secrets_match(a: &str, b: &str) -> bool {
+    a.as_bytes().ct_eq(b.as_bytes()).into()
+}
+
+/// Handles a request from one of the other servers. Returns false if the connection should be closed.
+pub async fn handle_custom_ipc(
+    connection: &mut InternalConnection,
+    data: &CustomIpcSegment,
+) -> bool {
+    // nothing else is allowed until they prove they're one of our servers
+    if !connection.authenticated {
+        let CustomIpcData::Authenticate { secret } = &data.data else {
+            tracing::warn!(
+                "{} sent custom IPC without authenticating first, disconnecting!",
+                connection.ip
+            );
+            return false;
+        };
+
+        // an empty secret would let anyone in, the server shouldn't have started with one anyway
+        connection.authenticated = !connection.config.internal_secret.is_empty()
+            && secrets_match(secret, &connection.config.internal_secret);
+        if !connection.authenticated {
+            tracing::warn!("{} sent the wrong internal secret!", connection.ip);
+        }
+
+        connection
+            .send_response(
+                data,
+                CustomIpcSegment {
+                    op_code: CustomIpcType::Authenticated,
+                    data: CustomIpcData::Authenticated {
+                        success: connection.authenticated,
+                    },
+                    ..Default::default()
+                },
+            )
+            .await;
+
+        return connection.authenticated;
+    }
 
-pub async fn handle_custom_ipc(connection: &mut ZoneConnection, data: &CustomIpcSegment) {
-    match &data.data {
+    let (op_code, response) = match &data.data {
         CustomIpcData::RequestCreateCharacter {
             service_account_id,
             name,
@@ -59,75 +99,40 @@ pub async fn handle_custom_ipc(connection: &mut ZoneConnection, data: &CustomIpc
             tracing::info!("Created new player: {content_id} {actor_id}");
 
             // send them the new actor and content id
-            {
-                connection
-                    .send_segment(PacketSegment {
-                        segment_type: SegmentType::KawariIpc,
-                        data: SegmentData::KawariIpc {
-                            data: CustomIpcSegment {
-                                op_code: CustomIpcType::CharacterCreated,
-                                data: CustomIpcData::CharacterCreated {
-                                    actor_id,
-                                    content_id,
-                                },
-                                ..Default::default()
-                            },
-                        },
-                        ..Default::default()
-                    })
-                    .await;
-            }
+            (
+                CustomIpcType::CharacterCreated,
+                CustomIpcData::CharacterCreated {
+                    actor_id,
+                    content_id,
+                },
+            )
         }
         CustomIpcData::GetActorId { content_id } => {
             let actor_id = connection.database.find_actor_id(*content_id);
 
             tracing::info!("We found an actor id: {actor_id}");
 
-            // send them the actor id
-            {
-                connection
-                    .send_segment(PacketSegment {
-                        segment_type: SegmentType::KawariIpc,
-                        data: SegmentData::KawariIpc {
-                            data: CustomIpcSegment {
-                                op_code: CustomIpcType::ActorIdFound,
-                                data: CustomIpcData::ActorIdFound { actor_id },
-                                ..Default::default()
-                            },
-                        },
-                        ..Default::default()
-                    })
-                    .await;
-            }
+            (
+                CustomIpcType::ActorIdFound,
+                CustomIpcData::ActorIdFound { actor_id },
+            )
         }
         CustomIpcData::CheckNameIsAvailable { name } => {
             let is_name_free = connection.database.check_is_name_free(name);
 
-            // send response
-            {
-                connection
-                    .send_segment(PacketSegment {
-                        segment_type: SegmentType::KawariIpc,
-                        data: SegmentData::KawariIpc {
-                            data: CustomIpcSegment {
-                                op_code: CustomIpcType::NameIsAvailableResponse,
-                                data: CustomIpcData::NameIsAvailableResponse { free: is_name_free },
-                                ..Default::default()
-                            },
-                        },
-                        ..Default::default()
-                    })
-                    .await;
-            }
+            (
+                CustomIpcType::NameIsAvailableResponse,
+                CustomIpcData::NameIsAvailableResponse { free: is_name_free },
+            )
         }
         CustomIpcData::RequestCharacterList { service_account_id } => {
-            let config = get_config();
+            let world_id = connection.config.world_id;
 
             let world_name;
             {
                 let mut game_data = connection.gamedata.lock().unwrap();
                 world_name = game_data
-                    .get_world_name(config.world.world_id)
+                    .get_world_name(world_id)
                     .expect("Couldn't read world name");
             }
 
@@ -137,68 +142,48 @@ pub async fn handle_custom_ipc(connection: &mut ZoneConnection, data: &CustomIpc
 
                 characters = connection.database.get_character_list(
                     *service_account_id,
-                    config.world.world_id,
+                    world_id,
                     &world_name,
                     &mut game_data,
                 );
             }
 
-            // send response
-            {
-                send_packet::<CustomIpcSegment>(
-                    &mut connection.socket,
-                    &mut connection.state,
-                    ConnectionType::None,
-                    CompressionType::Uncompressed,
-                    &[PacketSegment {
-                        segment_type: SegmentType::KawariIpc,
-                        data: SegmentData::KawariIpc {
-                            data: CustomIpcSegment {
-                                op_code: CustomIpcType::RequestCharacterListRepsonse,
-                                data: CustomIpcData::RequestCharacterListRepsonse { characters },
-                                ..Default::default()
-                            },
-                        },
-                        ..Default::default()
-                    }],
-                )
-                .await;
-            }
+            (
+                CustomIpcType::RequestCharacterListRepsonse,
+                CustomIpcData::RequestCharacterListRepsonse { characters },
+            )
         }
         CustomIpcData::DeleteCharacter { content_id } => {
-            connection.database.delete_character(*content_id);
-
-            // send response
-            {
-                send_packet::<CustomIpcSegment>(
-                    &mut connection.socket,
-                    &mut connection.state,
-                    ConnectionType::None,
-                    CompressionType::Uncompressed,
-                    &[PacketSegment {
-                        segment_type: SegmentType::KawariIpc,
-                        data: SegmentData::KawariIpc {
-                            data: CustomIpcSegment {
-                                op_code: CustomIpcType::CharacterDeleted,
-                                data: CustomIpcData::CharacterDeleted { deleted: 1 },
-                                ..Default::default()
-                            },
-                        },
-                        ..Default::default()
-                    }],
-                )
-                .await;
+            let deleted = connection.database.delete_character(*content_id);
+            if !deleted {
+                tracing::warn!("Asked to delete {content_id}, who doesn't exist!");
             }
+
+            (
+                CustomIpcType::CharacterDeleted,
+                CustomIpcData::CharacterDeleted {
+                    deleted: deleted as u8,
+                },
+            )
         }
         CustomIpcData::ImportCharacter {
             service_account_id,
             path,
         } => {
-            let mut game_data = connection.gamedata.lock().unwrap();
+            {
+                let mut game_data = connection.gamedata.lock().unwrap();
 
-            connection
-                .database
-                .import_character(&mut game_data, *service_account_id, path);
+                connection
+                    .database
+                    .import_character(&mut game_data, *service_account_id, path);
+            }
+
+            (
+                CustomIpcType::CharacterImported,
+                CustomIpcData::CharacterImported {
+                    service_account_id: *service_account_id,
+                },
+            )
         }
         CustomIpcData::RemakeCharacter {
             content_id,
@@ -214,32 +199,105 @@ pub async fn handle_custom_ipc(connection: &mut ZoneConnection, data: &CustomIpc
                 .database
                 .set_remake_mode(*content_id, RemakeMode::None);
 
-            // send response
-            {
-                send_packet::<CustomIpcSegment>(
-                    &mut connection.socket,
-                    &mut connection.state,
-                    ConnectionType::None,
-                    CompressionType::Uncompressed,
-                    &[PacketSegment {
-                        segment_type: SegmentType::KawariIpc,
-                        data: SegmentData::KawariIpc {
-                            data: CustomIpcSegment {
-                                op_code: CustomIpcType::CharacterRemade,
-                                data: CustomIpcData::CharacterRemade {
-                                    content_id: *content_id,
-                                },
-                                ..Default::default()
-                            },
-                        },
-                        ..Default::default()
-                    }],
-                )
-                .await;
-            }
+            (
+                CustomIpcType::CharacterRemade,
+                CustomIpcData::CharacterRemade {
+                    content_id: *content_id,
+                },
+            )
+        }
+        CustomIpcData::DeleteServiceAccount { service_account_id } => {
+            let num_deleted = connection
+                .database
+                .delete_service_account(*service_account_id);
+
+            tracing::info!(
+                "Deleted {num_deleted} characters belonging to service account {service_account_id}"
+            );
+
+            (
+                CustomIpcType::ServiceAccountDeleted,
+                CustomIpcData::ServiceAccountDeleted { num_deleted },
+            )
+        }
+        CustomIpcData::RenameCharacter { content_id, name } => {
+            let renamed = connection.database.rename_character(*content_id, name);
+
+            tracing::info!("Renaming {content_id} to {name}: {renamed}");
+
+            (
+                CustomIpcType::CharacterRenamed,
+                CustomIpcData::CharacterRenamed { renamed },
+            )
+        }
+        CustomIpcData::ExportCharacter { content_id } => {
+            let character = connection.database.export_character(*content_id);
+
+            (
+                CustomIpcType::CharacterExported,
+                CustomIpcData::CharacterExported {
+                    found: character.is_some(),
+                    character: character.unwrap_or_default(),
+                },
+            )
+        }
+        CustomIpcData::ImportTransferredCharacter {
+            service_account_id,
+            character,
+        } => {
+            let imported = connection
+                .database
+                .import_transferred_character(*service_account_id, character);
+
+            let data = match imported {
+                Some((content_id, actor_id)) => {
+                    tracing::info!(
+                        "Transferred {} to this world as {content_id}",
+                        character.name
+                    );
+
+                    CustomIpcData::TransferredCharacterImported {
+                        imported: true,
+                        content_id,
+                        actor_id,
+                    }
+                }
+                None => {
+                    tracing::info!(
+                        "Couldn't transfer {} to this world, their name is taken",
+                        character.name
+                    );
+
+                    CustomIpcData::TransferredCharacterImported {
+                        imported: false,
+                        content_id: 0,
+                        actor_id: 0,
+                    }
+                }
+            };
+
+            (CustomIpcType::TransferredCharacterImported, data)
         }
         _ => {
-            panic!("The server is recieving a response or unknown custom IPC!")
+            tracing::warn!(
+                "{} sent a response or unknown custom IPC {:?}, disconnecting!",
+                connection.ip,
+                data.op_code
+            );
+            return false;
         }
-    }
+    };
+
+    connection
+        .send_response(
+            data,
+            CustomIpcSegment {
+                op_code,
+                data: response,
+                ..Default::default()
+            },
+        )
+        .await;
+
+    true
 }
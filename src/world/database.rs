@@ -1,4 +1,7 @@
-use std::{io::Read, sync::Mutex};
+use std::{
+    io::Read,
+    sync::{Arc, Mutex},
+};
 
 use rusqlite::Connection;
 use serde::Deserialize;
@@ -6,20 +9,89 @@ use serde::Deserialize;
 use crate::{
     AETHERYTE_UNLOCK_BITMASK_SIZE, COMPLETED_QUEST_BITMASK_SIZE, UNLOCK_BITMASK_SIZE,
     common::{
-        CustomizeData, GameData, Position,
+        CustomizeData, GameData, Position, determine_initial_homepoint, is_valid_character_name,
+        run_migrations, value_to_flag_byte_index_value,
         workdefinitions::{CharaMake, ClientSelectData, RemakeMode},
     },
-    inventory::{Inventory, Item, Storage},
+    inventory::{ContainerType, Inventory, Item, Storage},
     ipc::{
+        kawari::{CharacterTransfer, TransferredItem},
         lobby::{CharacterDetails, CharacterFlag},
         zone::GameMasterRank,
     },
 };
 
-use super::PlayerData;
+use super::{ActorIdAllocator, PlayerData};
+
+/// Every change ever made to the schema, in order. See `run_migrations`.
+const MIGRATIONS: &[&str] = &[
+    // Initial schema
+    "CREATE TABLE IF NOT EXISTS characters (content_id INTEGER PRIMARY KEY, service_account_id INTEGER, actor_id INTEGER);
+    CREATE TABLE IF NOT EXISTS character_data (content_id INTEGER PRIMARY KEY, name STRING, chara_make STRING, city_state INTEGER, zone_id INTEGER, pos_x REAL, pos_y REAL, pos_z REAL, rotation REAL, inventory STRING, remake_mode INTEGER, gm_rank INTEGER, classjob_id INTEGER, classjob_levels STRING, classjob_exp STRING, unlocks STRING, aetherytes STRING, completed_quests STRING);",
+    // Move the inventory, class levels and unlocks out of JSON and into their own tables
+    "CREATE TABLE character_items (content_id INTEGER, container INTEGER, slot INTEGER, item_id INTEGER, quantity INTEGER, condition INTEGER, glamour_catalog_id INTEGER, PRIMARY KEY (content_id, container, slot));
+    CREATE TABLE character_classjobs (content_id INTEGER, classjob_index INTEGER, level INTEGER, exp INTEGER, PRIMARY KEY (content_id, classjob_index));
+    CREATE TABLE character_unlocks (content_id INTEGER, kind INTEGER, id INTEGER, PRIMARY KEY (content_id, kind, id));
+
+    WITH containers(container, path) AS (VALUES
+        (0, '$.pages[0].slots'), (1, '$.pages[1].slots'), (2, '$.pages[2].slots'), (3, '$.pages[3].slots'),
+        (3200, '$.armoury_off_hand.slots'), (3201, '$.armoury_head.slots'), (3202, '$.armoury_body.slots'),
+        (3203, '$.armoury_hands.slots'), (3205, '$.armoury_legs.slots'), (3206, '$.armoury_feet.slots'),
+        (3207, '$.armoury_earring.slots'), (3208, '$.armoury_necklace.slots'), (3209, '$.armoury_bracelet.slots'),
+        (3300, '$.armoury_rings.slots'), (3400, '$.armoury_soul_crystal.slots'), (3500, '$.armoury_main_hand.slots'))
+    INSERT INTO character_items
+        SELECT character_data.content_id, containers.container, slots.key, json_extract(slots.value, '$.id'), json_extract(slots.value, '$.quantity'), json_extract(slots.value, '$.condition'), json_extract(slots.value, '$.glamour_catalog_id')
+        FROM character_data, containers, json_each(character_data.inventory, containers.path) AS slots
+        WHERE json_extract(slots.value, '$.quantity') > 0;
+
+    WITH named_slots(container, slot, path) AS (VALUES
+        (1000, 0, '$.equipped.main_hand'), (1000, 1, '$.equipped.off_hand'), (1000, 2, '$.equipped.head'),
+        (1000, 3, '$.equipped.body'), (1000, 4, '$.equipped.hands'), (1000, 5, '$.equipped.belt'),
+        (1000, 6, '$.equipped.legs'), (1000, 7, '$.equipped.feet'), (1000, 8, '$.equipped.ears'),
+        (1000, 9, '$.equipped.neck'), (1000, 10, '$.equipped.wrists'), (1000, 11, '$.equipped.right_ring'),
+        (1000, 12, '$.equipped.left_ring'), (1000, 13, '$.equipped.soul_crystal'),
+        (2000, 0, '$.currency.gil'))
+    INSERT INTO character_items
+        SELECT character_data.content_id, named_slots.container, named_slots.slot, json_extract(character_data.inventory, named_slots.path || '.id'), json_extract(character_data.inventory, named_slots.path || '.quantity'), json_extract(character_data.inventory, named_slots.path || '.condition'), json_extract(character_data.inventory, named_slots.path || '.glamour_catalog_id')
+        FROM character_data, named_slots
+        WHERE json_extract(character_data.inventory, named_slots.path || '.quantity') > 0;
+
+    INSERT INTO character_classjobs
+        SELECT character_data.content_id, levels.key, levels.value, IFNULL(json_extract(character_data.classjob_exp, '$[' || levels.key || ']'), 0)
+        FROM character_data, json_each(character_data.classjob_levels) AS levels
+        WHERE levels.value != 0 OR IFNULL(json_extract(character_data.classjob_exp, '$[' || levels.key || ']'), 0) != 0;
+
+    WITH bits(bit) AS (VALUES (0), (1), (2), (3), (4), (5), (6), (7))
+    INSERT INTO character_unlocks
+        SELECT character_data.content_id, 0, bytes.key * 8 + bits.bit FROM character_data, json_each(character_data.unlocks) AS bytes, bits WHERE (bytes.value >> bits.bit) & 1
+        UNION ALL
+        SELECT character_data.content_id, 1, bytes.key * 8 + bits.bit FROM character_data, json_each(character_data.aetherytes) AS bytes, bits WHERE (bytes.value >> bits.bit) & 1
+        UNION ALL
+        SELECT character_data.content_id, 2, bytes.key * 8 + bits.bit FROM character_data, json_each(character_data.completed_quests) AS bytes, bits WHERE (bytes.value >> bits.bit) & 1;
+
+    ALTER TABLE character_data DROP COLUMN inventory;
+    ALTER TABLE character_data DROP COLUMN classjob_levels;
+    ALTER TABLE character_data DROP COLUMN classjob_exp;
+    ALTER TABLE character_data DROP COLUMN unlocks;
+    ALTER TABLE character_data DROP COLUMN aetherytes;
+    ALTER TABLE character_data DROP COLUMN completed_quests;",
+    // Home points, starting out as the aetheryte in their city-state
+    "ALTER TABLE character_data ADD COLUMN homepoint INTEGER NOT NULL DEFAULT 0;
+    UPDATE character_data SET homepoint = CASE city_state WHEN 1 THEN 8 WHEN 2 THEN 2 WHEN 3 THEN 9 ELSE 0 END;",
+];
+
+/// Which bitmask a row in `character_unlocks` belongs to.
+#[derive(Copy, Clone)]
+enum UnlockKind {
+    Unlock = 0,
+    Aetheryte = 1,
+    CompletedQuest = 2,
+}
 
 pub struct WorldDatabase {
     connection: Mutex<Connection>,
+    /// Shared with the server main loop, so players and NPCs never end up with the same actor id.
+    pub actor_ids: Arc<ActorIdAllocator>,
 }
 
 pub struct CharacterData {
@@ -38,22 +110,25 @@ impl Default for WorldDatabase {
 
 impl WorldDatabase {
     pub fn new() -> Self {
-        let connection = Connection::open("world.db").expect("Failed to open database!");
+        let mut connection = Connection::open("world.db").expect("Failed to open database!");
 
-        // Create characters table
-        {
-            let query = "CREATE TABLE IF NOT EXISTS characters (content_id INTEGER PRIMARY KEY, service_account_id INTEGER, actor_id INTEGER);";
-            connection.execute(query, ()).unwrap();
-        }
+        run_migrations(&mut connection, MIGRATIONS);
 
-        // Create characters data table
+        // Make sure new actors don't take the ids of existing characters
+        let actor_ids = Arc::new(ActorIdAllocator::default());
         {
-            let query = "CREATE TABLE IF NOT EXISTS character_data (content_id INTEGER PRIMARY KEY, name STRING, chara_make STRING, city_state INTEGER, zone_id INTEGER, pos_x REAL, pos_y REAL, pos_z REAL, rotation REAL, inventory STRING, remake_mode INTEGER, gm_rank INTEGER, classjob_id INTEGER, classjob_levels STRING, classjob_exp STRING, unlocks STRING, aetherytes STRING, completed_quests STRING);";
-            connection.execute(query, ()).unwrap();
+            let mut stmt = connection
+                .prepare("SELECT actor_id FROM characters")
+                .unwrap();
+            let existing_ids = stmt.query_map((), |row| row.get::<_, u32>(0)).unwrap();
+            for id in existing_ids {
+                actor_ids.reserve(id.unwrap());
+            }
         }
 
         Self {
             connection: Mutex::new(connection),
+            actor_ids,
         }
     }
 
@@ -172,6 +247,7 @@ impl WorldDatabase {
         );
 
         let mut player_data = self.find_player_data(actor_id);
+        let saved_player_data = player_data.clone();
 
         // import jobs
         for classjob in &character.classjob_levels {
@@ -262,7 +338,7 @@ impl WorldDatabase {
         player_data.unlocks = character.unlock_flags;
         player_data.aetherytes = character.unlock_aetherytes;
 
-        self.commit_player_data(&player_data);
+        self.commit_player_data(&player_data, &saved_player_data);
 
         tracing::info!("{} added to the world!", character.name);
     }
@@ -278,36 +354,17 @@ impl WorldDatabase {
             .unwrap();
 
         stmt = connection
-            .prepare("SELECT pos_x, pos_y, pos_z, rotation, zone_id, inventory, gm_rank, classjob_id, classjob_levels, classjob_exp, unlocks, aetherytes, completed_quests FROM character_data WHERE content_id = ?1")
+            .prepare("SELECT pos_x, pos_y, pos_z, rotation, zone_id, gm_rank, classjob_id, homepoint FROM character_data WHERE content_id = ?1")
             .unwrap();
-        let (
-            pos_x,
-            pos_y,
-            pos_z,
-            rotation,
-            zone_id,
-            inventory_json,
-            gm_rank,
-            classjob_id,
-            classjob_levels,
-            classjob_exp,
-            unlocks,
-            aetherytes,
-            completed_quests,
-        ): (
+        let (pos_x, pos_y, pos_z, rotation, zone_id, gm_rank, classjob_id, homepoint): (
             f32,
             f32,
             f32,
             f32,
             u16,
-            String,
             u8,
             i32,
-            String,
-            String,
-            String,
-            String,
-            String,
+            u32,
         ) = stmt
             .query_row((content_id,), |row| {
                 Ok((
@@ -319,16 +376,11 @@ impl WorldDatabase {
                     row.get(5)?,
                     row.get(6)?,
                     row.get(7)?,
-                    row.get(8)?,
-                    row.get(9)?,
-                    row.get(10)?,
-                    row.get(11)?,
-                    row.get(12)?,
                 ))
             })
             .unwrap();
 
-        let inventory = serde_json::from_str(&inventory_json).unwrap();
+        let (classjob_levels, classjob_exp) = Self::read_classjobs(&connection, content_id);
 
         PlayerData {
             actor_id,
@@ -341,41 +393,306 @@ impl WorldDatabase {
             },
             rotation,
             zone_id,
-            inventory,
+            homepoint,
+            inventory: Self::read_inventory(&connection, content_id),
             gm_rank: GameMasterRank::try_from(gm_rank).unwrap(),
             classjob_id: classjob_id as u8,
-            classjob_levels: serde_json::from_str(&classjob_levels).unwrap(),
-            classjob_exp: serde_json::from_str(&classjob_exp).unwrap(),
-            unlocks: serde_json::from_str(&unlocks).unwrap(),
-            aetherytes: serde_json::from_str(&aetherytes).unwrap(),
-            completed_quests: serde_json::from_str(&completed_quests).unwrap(),
+            classjob_levels,
+            classjob_exp,
+            unlocks: Self::read_bitmask(
+                &connection,
+                content_id,
+                UnlockKind::Unlock,
+                UNLOCK_BITMASK_SIZE,
+            ),
+            aetherytes: Self::read_bitmask(
+                &connection,
+                content_id,
+                UnlockKind::Aetheryte,
+                AETHERYTE_UNLOCK_BITMASK_SIZE,
+            ),
+            completed_quests: Self::read_bitmask(
+                &connection,
+                content_id,
+                UnlockKind::CompletedQuest,
+                COMPLETED_QUEST_BITMASK_SIZE,
+            ),
             ..Default::default()
         }
     }
 
-    /// Commit the dynamic player data back to the database
-    pub fn commit_player_data(&self, data: &PlayerData) {
-        let connection = self.connection.lock().unwrap();
+    /// Commit the dynamic player data back to the database. Only what changed since `saved` (what's currently in the database) is written.
+    pub fn commit_player_data(&self, data: &PlayerData, saved: &PlayerData) {
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = connection.transaction().unwrap();
+
+        if data.zone_id != saved.zone_id
+            || data.position != saved.position
+            || data.rotation != saved.rotation
+            || data.classjob_id != saved.classjob_id
+            || data.gm_rank != saved.gm_rank
+            || data.homepoint != saved.homepoint
+        {
+            let mut stmt = transaction
+                .prepare("UPDATE character_data SET zone_id=?1, pos_x=?2, pos_y=?3, pos_z=?4, rotation=?5, classjob_id=?6, gm_rank=?7, homepoint=?8 WHERE content_id = ?9")
+                .unwrap();
+            stmt.execute((
+                data.zone_id,
+                data.position.x,
+                data.position.y,
+                data.position.z,
+                data.rotation,
+                data.classjob_id,
+                data.gm_rank as u8,
+                data.homepoint,
+                data.content_id,
+            ))
+            .unwrap();
+        }
 
-        let mut stmt = connection
-            .prepare("UPDATE character_data SET zone_id=?1, pos_x=?2, pos_y=?3, pos_z=?4, rotation=?5, inventory=?6, classjob_id=?7, classjob_levels=?8, classjob_exp=?9, unlocks=?10, aetherytes=?11, completed_quests=?12 WHERE content_id = ?13")
-            .unwrap();
-        stmt.execute((
-            data.zone_id,
-            data.position.x,
-            data.position.y,
-            data.position.z,
-            data.rotation,
-            serde_json::to_string(&data.inventory).unwrap(),
-            data.classjob_id,
-            serde_json::to_string(&data.classjob_levels).unwrap(),
-            serde_json::to_string(&data.classjob_exp).unwrap(),
-            serde_json::to_string(&data.unlocks).unwrap(),
-            serde_json::to_string(&data.aetherytes).unwrap(),
-            serde_json::to_string(&data.completed_quests).unwrap(),
+        Self::write_inventory(
+            &transaction,
+            data.content_id,
+            &data.inventory,
+            &saved.inventory,
+        );
+        Self::write_classjobs(&transaction, data, saved);
+        Self::write_bitmask(
+            &transaction,
+            data.content_id,
+            UnlockKind::Unlock,
+            &data.unlocks,
+            &saved.unlocks,
+        );
+        Self::write_bitmask(
+            &transaction,
             data.content_id,
-        ))
-        .unwrap();
+            UnlockKind::Aetheryte,
+            &data.aetherytes,
+            &saved.aetherytes,
+        );
+        Self::write_bitmask(
+            &transaction,
+            data.content_id,
+            UnlockKind::CompletedQuest,
+            &data.completed_quests,
+            &saved.completed_quests,
+        );
+
+        transaction.commit().unwrap();
+    }
+
+    /// Reads every item a character has. Slots without a row are left empty.
+    fn read_inventory(connection: &Connection, content_id: u64) -> Inventory {
+        let mut inventory = Inventory::default();
+
+        let mut stmt = connection
+            .prepare("SELECT container, slot, item_id, quantity, condition, glamour_catalog_id FROM character_items WHERE content_id = ?1")
+            .unwrap();
+        let rows = stmt
+            .query_map((content_id,), |row| {
+                Ok((
+                    row.get::<_, u16>(0)?,
+                    row.get::<_, u16>(1)?,
+                    Item {
+                        id: row.get(2)?,
+                        quantity: row.get(3)?,
+                        condition: row.get(4)?,
+                        glamour_catalog_id: row.get(5)?,
+                    },
+                ))
+            })
+            .unwrap();
+
+        for row in rows {
+            let (container, slot, item) = row.unwrap();
+
+            let Ok(container_type) = ContainerType::try_from(container) else {
+                tracing::warn!(
+                    "{content_id} has an item in unknown container {container}, ignoring!"
+                );
+                continue;
+            };
+
+            let container = inventory.get_container_mut(&container_type);
+            if slot as u32 >= container.max_slots() {
+                tracing::warn!(
+                    "{content_id} has an item in invalid slot {slot} of {container_type:?}, ignoring!"
+                );
+                continue;
+            }
+
+            *container.get_slot_mut(slot) = item;
+        }
+
+        inventory
+    }
+
+    /// Writes any slots that changed between `saved` and `inventory`.
+    fn write_inventory(
+        connection: &Connection,
+        content_id: u64,
+        inventory: &Inventory,
+        saved: &Inventory,
+    ) {
+        let mut upsert = connection
+            .prepare("INSERT OR REPLACE INTO character_items VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)")
+            .unwrap();
+        let mut delete = connection
+            .prepare("DELETE FROM character_items WHERE content_id = ?1 AND container = ?2 AND slot = ?3")
+            .unwrap();
+
+        for ((container_type, container), (_, saved_container)) in inventory.into_iter().zip(saved)
+        {
+            for slot in 0..container.max_slots() as u16 {
+                let item = container.get_slot(slot);
+                if item == saved_container.get_slot(slot) {
+                    continue;
+                }
+
+                if item.quantity == 0 {
+                    delete
+                        .execute((content_id, container_type as u16, slot))
+                        .unwrap();
+                } else {
+                    upsert
+                        .execute((
+                            content_id,
+                            container_type as u16,
+                            slot,
+                            item.id,
+                            item.quantity,
+                            item.condition,
+                            item.glamour_catalog_id,
+                        ))
+                        .unwrap();
+                }
+            }
+        }
+    }
+
+    /// Reads the levels and exp of every class a character has. Classes without a row are level 0.
+    fn read_classjobs(connection: &Connection, content_id: u64) -> ([i32; 32], [u32; 32]) {
+        let mut levels = [0; 32];
+        let mut exp = [0; 32];
+
+        let mut stmt = connection
+            .prepare(
+                "SELECT classjob_index, level, exp FROM character_classjobs WHERE content_id = ?1",
+            )
+            .unwrap();
+        let rows = stmt
+            .query_map((content_id,), |row| {
+                Ok((row.get::<_, u32>(0)?, row.get(1)?, row.get(2)?))
+            })
+            .unwrap();
+
+        for row in rows {
+            let (index, level, class_exp) = row.unwrap();
+            let index = index as usize;
+            if index < levels.len() {
+                levels[index] = level;
+                exp[index] = class_exp;
+            }
+        }
+
+        (levels, exp)
+    }
+
+    /// Writes any classes whose level or exp changed between `saved` and `data`.
+    fn write_classjobs(connection: &Connection, data: &PlayerData, saved: &PlayerData) {
+        let mut upsert = connection
+            .prepare("INSERT OR REPLACE INTO character_classjobs VALUES (?1, ?2, ?3, ?4)")
+            .unwrap();
+        let mut delete = connection
+            .prepare(
+                "DELETE FROM character_classjobs WHERE content_id = ?1 AND classjob_index = ?2",
+            )
+            .unwrap();
+
+        for index in 0..data.classjob_levels.len() {
+            let level = data.classjob_levels[index];
+            let exp = data.classjob_exp[index];
+            if level == saved.classjob_levels[index] && exp == saved.classjob_exp[index] {
+                continue;
+            }
+
+            if level == 0 && exp == 0 {
+                delete.execute((data.content_id, index as u32)).unwrap();
+            } else {
+                upsert
+                    .execute((data.content_id, index as u32, level, exp))
+                    .unwrap();
+            }
+        }
+    }
+
+    /// Reads a bitmask, which is stored as one row per set bit. It's at least `size` bytes long.
+    fn read_bitmask(
+        connection: &Connection,
+        content_id: u64,
+        kind: UnlockKind,
+        size: usize,
+    ) -> Vec<u8> {
+        let mut bitmask = vec![0u8; size];
+
+        let mut stmt = connection
+            .prepare("SELECT id FROM character_unlocks WHERE content_id = ?1 AND kind = ?2")
+            .unwrap();
+        let ids = stmt
+            .query_map((content_id, kind as u8), |row| row.get::<_, u32>(0))
+            .unwrap();
+
+        for id in ids {
+            let (value, index) = value_to_flag_byte_index_value(id.unwrap());
+            let index = index as usize;
+            if index >= bitmask.len() {
+                bitmask.resize(index + 1, 0);
+            }
+            bitmask[index] |= value;
+        }
+
+        bitmask
+    }
+
+    /// Writes any bits that changed between `saved` and `bitmask`.
+    fn write_bitmask(
+        connection: &Connection,
+        content_id: u64,
+        kind: UnlockKind,
+        bitmask: &[u8],
+        saved: &[u8],
+    ) {
+        let mut insert = connection
+            .prepare("INSERT OR REPLACE INTO character_unlocks VALUES (?1, ?2, ?3)")
+            .unwrap();
+        let mut delete = connection
+            .prepare(
+                "DELETE FROM character_unlocks WHERE content_id = ?1 AND kind = ?2 AND id = ?3",
+            )
+            .unwrap();
+
+        for index in 0..bitmask.len().max(saved.len()) {
+            let byte = bitmask.get(index).copied().unwrap_or(0);
+            let saved_byte = saved.get(index).copied().unwrap_or(0);
+            let changed = byte ^ saved_byte;
+            if changed == 0 {
+                continue;
+            }
+
+            for bit in 0..8 {
+                if changed & (1 << bit) == 0 {
+                    continue;
+                }
+
+                let id = index as u32 * 8 + bit;
+                if byte & (1 << bit) != 0 {
+                    insert.execute((content_id, kind as u8, id)).unwrap();
+                } else {
+                    delete.execute((content_id, kind as u8, id)).unwrap();
+                }
+            }
+        }
     }
 
     // TODO: from/to sql int
@@ -421,11 +738,11 @@ impl WorldDatabase {
         for (index, (content_id, actor_id)) in content_actor_ids.iter().enumerate() {
             let mut stmt = connection
                 .prepare(
-                    "SELECT name, chara_make, zone_id, inventory, remake_mode, classjob_id, classjob_levels FROM character_data WHERE content_id = ?1",
+                    "SELECT name, chara_make, zone_id, remake_mode, classjob_id FROM character_data WHERE content_id = ?1",
                 )
                 .unwrap();
 
-            let result: Result<(String, String, u16, String, i32, i32, String), rusqlite::Error> =
+            let result: Result<(String, String, u16, i32, i32), rusqlite::Error> =
                 stmt.query_row((content_id,), |row| {
                     Ok((
                         row.get(0)?,
@@ -433,29 +750,19 @@ impl WorldDatabase {
                         row.get(2)?,
                         row.get(3)?,
                         row.get(4)?,
-                        row.get(5)?,
-                        row.get(6)?,
                     ))
                 });
 
-            if let Ok((
-                name,
-                chara_make,
-                zone_id,
-                inventory_json,
-                remake_mode,
-                classjob_id,
-                classjob_levels,
-            )) = result
-            {
+            if let Ok((name, chara_make, zone_id, remake_mode, classjob_id)) = result {
                 let chara_make = CharaMake::from_json(&chara_make);
 
-                let inventory: Inventory = serde_json::from_str(&inventory_json).unwrap();
+                let inventory = Self::read_inventory(&connection, *content_id as u64);
+                let (class_levels, _) = Self::read_classjobs(&connection, *content_id as u64);
 
                 let select_data = ClientSelectData {
                     character_name: name.clone(),
                     current_class: classjob_id,
-                    class_levels: serde_json::from_str(&classjob_levels).unwrap(),
+                    class_levels,
                     race: chara_make.customize.race as i32,
                     subrace: chara_make.customize.subrace as i32,
                     gender: chara_make.customize.gender as i32,
@@ -503,12 +810,17 @@ impl WorldDatabase {
         characters
     }
 
-    fn generate_content_id() -> u32 {
-        fastrand::u32(..)
-    }
+    fn generate_content_id(connection: &Connection) -> u32 {
+        let mut stmt = connection
+            .prepare("SELECT 1 FROM characters WHERE content_id = ?1")
+            .unwrap();
 
-    fn generate_actor_id() -> u32 {
-        fastrand::u32(..)
+        loop {
+            let content_id = fastrand::u32(..);
+            if !stmt.exists((content_id,)).unwrap() {
+                return content_id;
+            }
+        }
     }
 
     /// Gives (content_id, actor_id)
@@ -521,29 +833,17 @@ impl WorldDatabase {
         zone_id: u16,
         inventory: Inventory,
     ) -> (u64, u32) {
-        let content_id = Self::generate_content_id();
-        let actor_id = Self::generate_actor_id();
+        let mut connection = self.connection.lock().unwrap();
 
-        let connection = self.connection.lock().unwrap();
+        let content_id = Self::generate_content_id(&connection);
+        let actor_id = self.actor_ids.allocate_player();
 
-        // fill out the initial classjob
         let chara_make = CharaMake::from_json(chara_make_str);
-        let mut classjob_levels = [0i32; 32];
-        classjob_levels[chara_make.classjob_id as usize] = 1; // inital level
-
-        let classjob_exp = [0u32; 32];
-
-        // fill out initial unlocks
-        let unlocks = vec![0u8; UNLOCK_BITMASK_SIZE];
 
-        // fill out initial aetherytes
-        let aetherytes = vec![0u8; AETHERYTE_UNLOCK_BITMASK_SIZE];
-
-        // fill out initial completed quests`
-        let completed_quests = vec![0u8; COMPLETED_QUEST_BITMASK_SIZE];
+        let transaction = connection.transaction().unwrap();
 
         // insert ids
-        connection
+        transaction
             .execute(
                 "INSERT INTO characters VALUES (?1, ?2, ?3);",
                 (content_id, service_account_id, actor_id),
@@ -551,26 +851,41 @@ impl WorldDatabase {
             .unwrap();
 
         // insert char data
-        connection
+        transaction
             .execute(
-                "INSERT INTO character_data VALUES (?1, ?2, ?3, ?4, ?5, 0.0, 0.0, 0.0, 0.0, ?6, 0, 90, ?7, ?8, ?9, ?10, ?11, ?12);",
+                "INSERT INTO character_data (content_id, name, chara_make, city_state, zone_id, pos_x, pos_y, pos_z, rotation, remake_mode, gm_rank, classjob_id, homepoint) VALUES (?1, ?2, ?3, ?4, ?5, 0.0, 0.0, 0.0, 0.0, 0, 90, ?6, ?7);",
                 (
                     content_id,
                     name,
                     chara_make_str,
                     city_state,
                     zone_id,
-                    serde_json::to_string(&inventory).unwrap(),
                     chara_make.classjob_id,
-                    serde_json::to_string(&classjob_levels).unwrap(),
-                    serde_json::to_string(&classjob_exp).unwrap(),
-                    serde_json::to_string(&unlocks).unwrap(),
-                    serde_json::to_string(&aetherytes).unwrap(),
-                    serde_json::to_string(&completed_quests).unwrap(),
+                    determine_initial_homepoint(city_state),
                 ),
             )
             .unwrap();
 
+        // an empty inventory has no rows, so only the starting items need to be written
+        Self::write_inventory(
+            &transaction,
+            content_id as u64,
+            &inventory,
+            &Inventory::default(),
+        );
+
+        // fill out the initial classjob
+        transaction
+            .execute(
+                "INSERT INTO character_classjobs VALUES (?1, ?2, 1, 0);",
+                (content_id, chara_make.classjob_id),
+            )
+            .unwrap();
+
+        // unlocks, aetherytes and completed quests all start empty, so there's nothing else to write
+
+        transaction.commit().unwrap();
+
         (content_id as u64, actor_id)
     }
 
@@ -628,25 +943,276 @@ impl WorldDatabase {
         }
     }
 
-    /// Deletes a character and all associated data
-    pub fn delete_character(&self, content_id: u64) {
-        let connection = self.connection.lock().unwrap();
+    /// Deletes a character and all associated data. Returns false if there's no such character.
+    pub fn delete_character(&self, content_id: u64) -> bool {
+        let mut connection = self.connection.lock().unwrap();
+
+        let actor_id: u32 = {
+            let mut stmt = connection
+                .prepare("SELECT actor_id FROM characters WHERE content_id = ?1")
+                .unwrap();
+            match stmt.query_row((content_id,), |row| row.get(0)) {
+                Ok(actor_id) => actor_id,
+                Err(_) => return false,
+            }
+        };
+
+        // all or nothing, so a failure halfway through can't leave bits of them behind
+        let transaction = connection.transaction().unwrap();
 
         // delete data
+        for table in [
+            "character_data",
+            "character_items",
+            "character_classjobs",
+            "character_unlocks",
+        ] {
+            transaction
+                .execute(
+                    &format!("DELETE FROM {table} WHERE content_id = ?1"),
+                    (content_id,),
+                )
+                .unwrap();
+        }
+
+        // delete char
+        transaction
+            .execute(
+                "DELETE FROM characters WHERE content_id = ?1",
+                (content_id,),
+            )
+            .unwrap();
+
+        transaction.commit().unwrap();
+
+        self.actor_ids.release(actor_id);
+
+        true
+    }
+
+    /// Deletes every character belonging to a service account, and returns how many there were.
+    pub fn delete_service_account(&self, service_account_id: u32) -> u32 {
+        let content_ids: Vec<u64>;
         {
+            let connection = self.connection.lock().unwrap();
+
             let mut stmt = connection
-                .prepare("DELETE FROM character_data WHERE content_id = ?1")
+                .prepare("SELECT content_id FROM characters WHERE service_account_id = ?1")
                 .unwrap();
-            stmt.execute((content_id,)).unwrap();
+            content_ids = stmt
+                .query_map((service_account_id,), |row| row.get(0))
+                .unwrap()
+                .map(|x| x.unwrap())
+                .collect();
         }
 
-        // delete char
+        for content_id in &content_ids {
+            self.delete_character(*content_id);
+        }
+
+        content_ids.len() as u32
+    }
+
+    /// Renames a character, returns false if the name isn't valid or someone else already has it.
+    pub fn rename_character(&self, content_id: u64, name: &str) -> bool {
+        if !is_valid_character_name(name) {
+            return false;
+        }
+
+        let connection = self.connection.lock().unwrap();
+
+        // checked while holding the lock, so nobody can take the name in the meantime
         {
             let mut stmt = connection
-                .prepare("DELETE FROM characters WHERE content_id = ?1")
+                .prepare("SELECT content_id FROM character_data WHERE name = ?1")
                 .unwrap();
-            stmt.execute((content_id,)).unwrap();
+            if stmt.exists((name,)).unwrap() {
+                return false;
+            }
         }
+
+        let mut stmt = connection
+            .prepare("UPDATE character_data SET name = ?1 WHERE content_id = ?2")
+            .unwrap();
+        stmt.execute((name, content_id)).unwrap() == 1
+    }
+
+    /// Gathers everything about a character so they can be moved to another world. Returns None if they don't exist.
+    pub fn export_character(&self, content_id: u64) -> Option<CharacterTransfer> {
+        let connection = self.connection.lock().unwrap();
+
+        let mut stmt = connection
+            .prepare("SELECT name, chara_make, city_state, zone_id, pos_x, pos_y, pos_z, rotation, remake_mode, gm_rank, classjob_id, homepoint FROM character_data WHERE content_id = ?1")
+            .unwrap();
+        let mut character = stmt
+            .query_row((content_id,), |row| {
+                Ok(CharacterTransfer {
+                    name: row.get(0)?,
+                    chara_make_json: row.get(1)?,
+                    city_state: row.get(2)?,
+                    zone_id: row.get(3)?,
+                    position: Position {
+                        x: row.get(4)?,
+                        y: row.get(5)?,
+                        z: row.get(6)?,
+                    },
+                    rotation: row.get(7)?,
+                    remake_mode: row.get(8)?,
+                    gm_rank: row.get(9)?,
+                    classjob_id: row.get(10)?,
+                    homepoint: row.get(11)?,
+                    ..Default::default()
+                })
+            })
+            .ok()?;
+
+        stmt = connection
+            .prepare("SELECT container, slot, item_id, quantity, condition, glamour_catalog_id FROM character_items WHERE content_id = ?1")
+            .unwrap();
+        character.items = stmt
+            .query_map((content_id,), |row| {
+                Ok(TransferredItem {
+                    container: row.get(0)?,
+                    slot: row.get(1)?,
+                    id: row.get(2)?,
+                    quantity: row.get(3)?,
+                    condition: row.get(4)?,
+                    glamour_catalog_id: row.get(5)?,
+                })
+            })
+            .unwrap()
+            .map(|x| x.unwrap())
+            .collect();
+
+        (character.classjob_levels, character.classjob_exp) =
+            Self::read_classjobs(&connection, content_id);
+        character.unlocks = Self::read_bitmask(
+            &connection,
+            content_id,
+            UnlockKind::Unlock,
+            UNLOCK_BITMASK_SIZE,
+        );
+        character.aetherytes = Self::read_bitmask(
+            &connection,
+            content_id,
+            UnlockKind::Aetheryte,
+            AETHERYTE_UNLOCK_BITMASK_SIZE,
+        );
+        character.completed_quests = Self::read_bitmask(
+            &connection,
+            content_id,
+            UnlockKind::CompletedQuest,
+            COMPLETED_QUEST_BITMASK_SIZE,
+        );
+
+        Some(character)
+    }
+
+    /// Adds a character exported from another world, with new content and actor ids.
+    /// Gives (content_id, actor_id), or None if their name is already taken here.
+    pub fn import_transferred_character(
+        &self,
+        service_account_id: u32,
+        character: &CharacterTransfer,
+    ) -> Option<(u64, u32)> {
+        let mut connection = self.connection.lock().unwrap();
+
+        // checked while holding the lock, so nobody can take the name in the meantime
+        {
+            let mut stmt = connection
+                .prepare("SELECT content_id FROM character_data WHERE name = ?1")
+                .unwrap();
+            if stmt.exists((&character.name,)).unwrap() {
+                return None;
+            }
+        }
+
+        let content_id = Self::generate_content_id(&connection);
+        let actor_id = self.actor_ids.allocate_player();
+
+        let transaction = connection.transaction().unwrap();
+
+        transaction
+            .execute(
+                "INSERT INTO characters VALUES (?1, ?2, ?3);",
+                (content_id, service_account_id, actor_id),
+            )
+            .unwrap();
+
+        transaction
+            .execute(
+                "INSERT INTO character_data (content_id, name, chara_make, city_state, zone_id, pos_x, pos_y, pos_z, rotation, remake_mode, gm_rank, classjob_id, homepoint) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13);",
+                (
+                    content_id,
+                    &character.name,
+                    &character.chara_make_json,
+                    character.city_state,
+                    character.zone_id,
+                    character.position.x,
+                    character.position.y,
+                    character.position.z,
+                    character.rotation,
+                    character.remake_mode,
+                    character.gm_rank,
+                    character.classjob_id,
+                    character.homepoint,
+                ),
+            )
+            .unwrap();
+
+        {
+            let mut stmt = transaction
+                .prepare(
+                    "INSERT OR REPLACE INTO character_items VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                )
+                .unwrap();
+            for item in &character.items {
+                stmt.execute((
+                    content_id,
+                    item.container,
+                    item.slot,
+                    item.id,
+                    item.quantity,
+                    item.condition,
+                    item.glamour_catalog_id,
+                ))
+                .unwrap();
+            }
+        }
+
+        // nothing is stored for them yet, so write everything against an empty character
+        let data = PlayerData {
+            content_id: content_id as u64,
+            classjob_levels: character.classjob_levels,
+            classjob_exp: character.classjob_exp,
+            ..Default::default()
+        };
+        Self::write_classjobs(&transaction, &data, &PlayerData::default());
+        Self::write_bitmask(
+            &transaction,
+            content_id as u64,
+            UnlockKind::Unlock,
+            &character.unlocks,
+            &[],
+        );
+        Self::write_bitmask(
+            &transaction,
+            content_id as u64,
+            UnlockKind::Aetheryte,
+            &character.aetherytes,
+            &[],
+        );
+        Self::write_bitmask(
+            &transaction,
+            content_id as u64,
+            UnlockKind::CompletedQuest,
+            &character.completed_quests,
+            &[],
+        );
+
+        transaction.commit().unwrap();
+
+        Some((content_id as u64, actor_id))
     }
 
     /// Sets the remake mode for a character
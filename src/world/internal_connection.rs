@@ -0,0 +1,65 @@
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+
+use tokio::net::TcpStream;
+
+use crate::{
+    common::GameData,
+    config::WorldConfig,
+    ipc::kawari::CustomIpcSegment,
+    packet::{
+        CompressionType, ConnectionType, PacketSegment, PacketState, SegmentData, SegmentType,
+        parse_packet, read_packet, send_packet,
+    },
+};
+
+use super::WorldDatabase;
+
+/// Represents a single connection from one of the other servers (e.g. lobby or login) on the internal port.
+pub struct InternalConnection {
+    pub config: WorldConfig,
+    pub socket: TcpStream,
+
+    pub state: PacketState,
+
+    pub ip: SocketAddr,
+
+    /// Whether they've sent us the right shared secret yet.
+    pub authenticated: bool,
+
+    pub database: Arc<WorldDatabase>,
+    pub gamedata: Arc<Mutex<GameData>>,
+}
+
+impl InternalConnection {
+    /// Reads the next packet, or returns None if the connection was closed or sent garbage.
+    pub async fn read_segments(&mut self) -> Option<Vec<PacketSegment<CustomIpcSegment>>> {
+        let data = read_packet(&mut self.socket).await.ok()?;
+        let (segments, _) = parse_packet(&data, &mut self.state);
+        Some(segments)
+    }
+
+    /// Sends a response to `request`, using the same request id.
+    pub async fn send_response(
+        &mut self,
+        request: &CustomIpcSegment,
+        mut response: CustomIpcSegment,
+    ) {
+        response.set_request_id(request.request_id());
+
+        send_packet(
+            &mut self.socket,
+            &mut self.state,
+            ConnectionType::None,
+            CompressionType::Uncompressed,
+            &[PacketSegment {
+                segment_type: SegmentType::KawariIpc,
+                data: SegmentData::KawariIpc { data: response },
+                ..Default::default()
+            }],
+        )
+        .await;
+    }
+}
@@ -1,3 +1,5 @@
+use std::collections::HashMap;
+
 use mlua::{FromLua, Lua, LuaSerdeExt, UserData, UserDataFields, UserDataMethods, Value};
 
 use crate::{
@@ -19,7 +21,7 @@ use crate::{
 use super::{PlayerData, StatusEffects, Zone, connection::TeleportQuery};
 
 pub enum Task {
-    ChangeTerritory { zone_id: u16 },
+    ChangeTerritory { zone_id: u16, private: bool },
     SetRemakeMode(RemakeMode),
     Warp { warp_id: u32 },
     BeginLogOut,
@@ -37,6 +39,7 @@ pub enum Task {
     UnlockOrchestrion { id: u16, on: bool },
     AddItem { id: u32 },
     CompleteAllQuests {},
+    SetHomepoint { id: u32 },
 }
 
 #[derive(Default, Clone)]
@@ -306,8 +309,9 @@ impl LuaPlayer {
         });
     }
 
-    fn change_territory(&mut self, zone_id: u16) {
-        self.queued_tasks.push(Task::ChangeTerritory { zone_id });
+    fn change_territory(&mut self, zone_id: u16, private: bool) {
+        self.queued_tasks
+            .push(Task::ChangeTerritory { zone_id, private });
     }
 
     fn set_remake_mode(&mut self, mode: RemakeMode) {
@@ -374,6 +378,10 @@ impl LuaPlayer {
     fn complete_all_quests(&mut self) {
         self.queued_tasks.push(Task::CompleteAllQuests {});
     }
+
+    fn set_homepoint(&mut self, id: u32) {
+        self.queued_tasks.push(Task::SetHomepoint { id });
+    }
 }
 
 impl UserData for LuaPlayer {
@@ -444,10 +452,13 @@ impl UserData for LuaPlayer {
             this.toggle_invisiblity();
             Ok(())
         });
-        methods.add_method_mut("change_territory", |_, this, zone_id: u16| {
-            this.change_territory(zone_id);
-            Ok(())
-        });
+        methods.add_method_mut(
+            "change_territory",
+            |_, this, (zone_id, private): (u16, Option<bool>)| {
+                this.change_territory(zone_id, private.unwrap_or(false));
+                Ok(())
+            },
+        );
         methods.add_method_mut("set_remake_mode", |lua, this, mode: Value| {
             let mode: RemakeMode = lua.from_value(mode).unwrap();
             this.set_remake_mode(mode);
@@ -505,6 +516,10 @@ impl UserData for LuaPlayer {
             this.complete_all_quests();
             Ok(())
         });
+        methods.add_method_mut("set_homepoint", |_, this, id: u32| {
+            this.set_homepoint(id);
+            Ok(())
+        });
     }
 
     fn add_fields<F: UserDataFields<Self>>(fields: &mut F) {
@@ -524,6 +539,7 @@ impl UserData for LuaPlayer {
             Ok(this.player_data.inventory.clone())
         });
         fields.add_field_method_get("zone", |_, this| Ok(this.zone_data.clone()));
+        fields.add_field_method_get("homepoint", |_, this| Ok(this.player_data.homepoint));
         // Helper method to reduce the amount of typing for gil
         fields.add_field_method_get("gil", |_, this| {
             Ok(this.player_data.inventory.currency.gil.quantity)
@@ -650,6 +666,8 @@ impl UserData for Zone {
 #[derive(Clone, Debug, Default)]
 pub struct EffectsBuilder {
     pub effects: Vec<ActionEffect>,
+    /// How long each status effect given by `gain_effect` lasts, keyed by effect id.
+    pub effect_durations: HashMap<u16, f32>,
 }
 
 impl UserData for EffectsBuilder {
@@ -672,6 +690,42 @@ impl UserData for EffectsBuilder {
             });
             Ok(())
         });
+        methods.add_method_mut("heal", |_, this, amount: u16| {
+            this.effects.push(ActionEffect {
+                kind: EffectKind::Heal {
+                    unk1: 0,
+                    unk2: 0,
+                    unk3: 0,
+                    unk4: 0,
+                    unk5: 0,
+                    amount,
+                },
+            });
+            Ok(())
+        });
+        methods.add_method_mut("miss", |_, this, _: ()| {
+            this.effects.push(ActionEffect {
+                kind: EffectKind::Miss,
+            });
+            Ok(())
+        });
+        methods.add_method_mut(
+            "gain_effect",
+            |_, this, (effect_id, duration): (u16, f32)| {
+                this.effects.push(ActionEffect {
+                    kind: EffectKind::GainEffect {
+                        unk1: 0,
+                        unk2: 0,
+                        unk3: 0,
+                        unk4: 0,
+                        unk5: 0,
+                        effect_id,
+                    },
+                });
+                this.effect_durations.insert(effect_id, duration);
+                Ok(())
+            },
+        );
     }
 }
 
@@ -716,6 +770,15 @@ pub fn load_init_script(lua: &mut Lua) -> mlua::Result<()> {
             Ok(())
         })?;
 
+    let register_battle_npc_func =
+        lua.create_function(|lua, (bnpc_base, battle_npc_script): (u32, String)| {
+            let mut state = lua.app_data_mut::<ExtraLuaState>().unwrap();
+            let _ = state
+                .battle_npc_scripts
+                .insert(bnpc_base, battle_npc_script);
+            Ok(())
+        })?;
+
     lua.set_app_data(ExtraLuaState::default());
     lua.globals().set("registerAction", register_action_func)?;
     lua.globals().set("registerEvent", register_event_func)?;
@@ -723,6 +786,8 @@ pub fn load_init_script(lua: &mut Lua) -> mlua::Result<()> {
         .set("registerCommand", register_command_func)?;
     lua.globals()
         .set("registerGMCommand", register_gm_command_func)?;
+    lua.globals()
+        .set("registerBattleNpc", register_battle_npc_func)?;
 
     let effectsbuilder_constructor = lua.create_function(|_, ()| Ok(EffectsBuilder::default()))?;
     lua.globals()
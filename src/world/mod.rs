@@ -7,6 +7,12 @@ pub use chat_handler::ChatHandler;
 mod connection;
 pub use connection::{ExtraLuaState, PlayerData, ZoneConnection};
 
+mod chat_connection;
+pub use chat_connection::ChatConnection;
+
+mod internal_connection;
+pub use internal_connection::InternalConnection;
+
 mod database;
 pub use database::{CharacterData, WorldDatabase};
 
@@ -19,9 +25,21 @@ pub use event::Event;
 mod actor;
 pub use actor::Actor;
 
+mod actor_ids;
+pub use actor_ids::ActorIdAllocator;
+
+mod battle_npc;
+pub use battle_npc::{
+    BattleNpc, BattleNpcBehaviour, BattleNpcScript, BattleNpcState, default_auto_attack,
+    estimate_battle_npc_hp, step_towards,
+};
+
 mod status_effects;
 pub use status_effects::StatusEffects;
 
+mod rcon;
+pub use rcon::RconCommand;
+
 mod server;
 pub use server::server_main_loop;
 
@@ -29,4 +47,4 @@ mod custom_ipc_handler;
 pub use custom_ipc_handler::handle_custom_ipc;
 
 mod common;
-pub use common::{ClientHandle, ClientId, FromServer, ServerHandle, ToServer};
+pub use common::{ClientHandle, ClientId, FromServer, InstanceRequest, ServerHandle, ToServer};
@@ -0,0 +1,143 @@
+use crate::ipc::zone::GameMasterRank;
+
+/// Shown when the RCON client asks for help, or sends something we don't understand.
+const RCON_HELP: &str = "Available commands:
+players - list everyone who is online
+kick <name> - disconnect a player
+broadcast <message> - send a message to everyone
+teleport <name> <zone id> - move a player to another zone
+reloadscripts - reload Init.lua and everything it registers
+setgmrank <name> <rank> - change a player's GM rank
+saveall - write every online player back to the database";
+
+/// A command sent over the RCON console.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RconCommand {
+    /// List everyone who is online.
+    ListPlayers,
+    /// Disconnect the player with this name.
+    Kick(String),
+    /// Send a message to everyone who is online.
+    Broadcast(String),
+    /// Move the player with this name to another zone.
+    Teleport(String, u16),
+    /// Reload Init.lua, and all of the scripts it registers.
+    ReloadScripts,
+    /// Change the GM rank of the player with this name.
+    SetGmRank(String, GameMasterRank),
+    /// Write every online player back to the database.
+    SaveAll,
+}
+
+impl RconCommand {
+    /// Parses a command line, returning a message to send back if it isn't valid.
+    pub fn parse(line: &str) -> Result<Self, String> {
+        let line = line.trim();
+        let (command, args) = line.split_once(' ').unwrap_or((line, ""));
+        let args = args.trim();
+
+        match command.to_ascii_lowercase().as_str() {
+            "players" => Ok(Self::ListPlayers),
+            "kick" => Ok(Self::Kick(required(args, "kick <name>")?.to_string())),
+            "broadcast" => Ok(Self::Broadcast(
+                required(args, "broadcast <message>")?.to_string(),
+            )),
+            "teleport" => {
+                // names have a space in them, so the zone id is always the last argument
+                let (name, zone_id) =
+                    split_last(args).ok_or("Usage: teleport <name> <zone id>".to_string())?;
+                let zone_id = zone_id
+                    .parse()
+                    .map_err(|_| format!("{zone_id} is not a valid zone id"))?;
+
+                Ok(Self::Teleport(name.to_string(), zone_id))
+            }
+            "reloadscripts" => Ok(Self::ReloadScripts),
+            "setgmrank" => {
+                let (name, rank) =
+                    split_last(args).ok_or("Usage: setgmrank <name> <rank>".to_string())?;
+                let rank = rank
+                    .parse::<u8>()
+                    .ok()
+                    .and_then(|rank| GameMasterRank::try_from(rank).ok())
+                    .ok_or(format!("{rank} is not a valid GM rank"))?;
+
+                Ok(Self::SetGmRank(name.to_string(), rank))
+            }
+            "saveall" => Ok(Self::SaveAll),
+            "" | "help" => Err(RCON_HELP.to_string()),
+            _ => Err(format!("Unknown command {command}\n{RCON_HELP}")),
+        }
+    }
+}
+
+/// Returns the arguments, or the usage if there are none.
+fn required<'a>(args: &'a str, usage: &str) -> Result<&'a str, String> {
+    if args.is_empty() {
+        Err(format!("Usage: {usage}"))
+    } else {
+        Ok(args)
+    }
+}
+
+/// Splits off the last argument, returning None if there's fewer than two.
+fn split_last(args: &str) -> Option<(&str, &str)> {
+    let (rest, last) = args.rsplit_once(' ')?;
+    let rest = rest.trim();
+    if rest.is_empty() {
+        None
+    } else {
+        Some((rest, last))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_simple_commands() {
+        assert_eq!(RconCommand::parse("players"), Ok(RconCommand::ListPlayers));
+        assert_eq!(RconCommand::parse("  SaveAll "), Ok(RconCommand::SaveAll));
+        assert_eq!(
+            RconCommand::parse("reloadscripts"),
+            Ok(RconCommand::ReloadScripts)
+        );
+    }
+
+    #[test]
+    fn parse_commands_with_names() {
+        assert_eq!(
+            RconCommand::parse("kick Test Character"),
+            Ok(RconCommand::Kick("Test Character".to_string()))
+        );
+        assert_eq!(
+            RconCommand::parse("broadcast Restarting in 5 minutes!"),
+            Ok(RconCommand::Broadcast(
+                "Restarting in 5 minutes!".to_string()
+            ))
+        );
+        assert_eq!(
+            RconCommand::parse("teleport Test Character 132"),
+            Ok(RconCommand::Teleport("Test Character".to_string(), 132))
+        );
+        assert_eq!(
+            RconCommand::parse("setgmrank Test Character 90"),
+            Ok(RconCommand::SetGmRank(
+                "Test Character".to_string(),
+                GameMasterRank::Debug
+            ))
+        );
+    }
+
+    #[test]
+    fn parse_invalid_commands() {
+        assert_eq!(RconCommand::parse(""), Err(RCON_HELP.to_string()));
+        assert_eq!(RconCommand::parse("help"), Err(RCON_HELP.to_string()));
+        assert!(RconCommand::parse("dance").is_err());
+        assert!(RconCommand::parse("kick").is_err());
+        assert!(RconCommand::parse("teleport 132").is_err());
+        assert!(RconCommand::parse("teleport Test Character limsa").is_err());
+        assert!(RconCommand::parse("setgmrank Test Character 2").is_err());
+    }
+}
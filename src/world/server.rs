@@ -1,19 +1,59 @@
+use mlua::Lua;
 use std::{
     collections::HashMap,
     sync::{Arc, Mutex},
-    time::Duration,
+    time::{Duration, Instant},
+};
+use tokio::{
+    sync::{mpsc::Receiver, oneshot},
+    time::MissedTickBehavior,
 };
-use tokio::sync::mpsc::Receiver;
 
 use crate::{
-    common::{CustomizeData, GameData, ObjectId, ObjectTypeId},
-    ipc::zone::{
-        ActorControl, ActorControlCategory, ActorControlSelf, ActorControlTarget, BattleNpcSubKind,
-        ClientTriggerCommand, CommonSpawn, NpcSpawn, ObjectKind,
+    common::{CustomizeData, GameData, ObjectId, ObjectTypeId, Position},
+    config::get_config,
+    ipc::{
+        chat::ChatChannel,
+        zone::{
+            ActionEffect, ActionRequest, ActionResult, ActorControl, ActorControlCategory,
+            ActorControlSelf, ActorControlTarget, BattleNpcSubKind, CharacterMode,
+            ClientTriggerCommand, CommonSpawn, EffectKind, NpcSpawn, ObjectKind,
+        },
     },
 };
 
-use super::{Actor, ClientHandle, ClientId, FromServer, ToServer};
+use super::{
+    Actor, ActorIdAllocator, BattleNpc, BattleNpcScript, BattleNpcState, ClientHandle, ClientId,
+    EffectsBuilder, ExtraLuaState, FromServer, InstanceRequest, RconCommand, ToServer, Zone,
+    default_auto_attack, estimate_battle_npc_hp, load_init_script, step_towards,
+};
+
+/// How far away (in yalms) someone can be and still hear /say.
+const SAY_RANGE: f32 = 20.0;
+/// How far away (in yalms) someone can be and still hear /yell.
+const YELL_RANGE: f32 = 100.0;
+/// How far away (in yalms) someone can be and still see an action being used.
+const ACTION_RANGE: f32 = 50.0;
+/// How long a dead NPC lies around before despawning.
+const CORPSE_DESPAWN_TIME: Duration = Duration::from_secs(10);
+/// How often the world is simulated. A tick taking longer than this is logged.
+const WORLD_TICK_INTERVAL: Duration = Duration::from_millis(100);
+/// How often players regenerate HP and MP.
+const REGEN_INTERVAL: Duration = Duration::from_secs(3);
+/// How much of their max HP and MP players regenerate, in percent, while nothing is fighting them.
+const REGEN_PERCENT: u32 = 10;
+/// How much of their max MP players regenerate, in percent, while something is fighting them.
+const REGEN_PERCENT_IN_COMBAT: u32 = 2;
+/// How often clients are told to count down their status effects.
+const STATUS_EFFECT_TICK_INTERVAL: Duration = Duration::from_secs(1);
+/// How often instances check if their weather changed.
+const WEATHER_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+/// The auto-attack action, which is also used for its animation.
+const AUTO_ATTACK_ACTION_ID: u32 = 7;
+/// The aggression mode of battle NPCs that only attack when attacked first.
+const PASSIVE_AGGRESSION_MODE: u8 = 1;
+/// How many effects fit in a single action result.
+const MAX_ACTION_EFFECTS: usize = 8;
 
 /// Used for the debug NPC.
 pub const CUSTOMIZE_DATA: CustomizeData = CustomizeData {
@@ -51,10 +91,44 @@ enum NetworkedActor {
     Npc(NpcSpawn),
 }
 
+impl NetworkedActor {
+    fn common(&self) -> &CommonSpawn {
+        match self {
+            NetworkedActor::Player(spawn) => &spawn.common,
+            NetworkedActor::Npc(spawn) => &spawn.common,
+        }
+    }
+
+    fn common_mut(&mut self) -> &mut CommonSpawn {
+        match self {
+            NetworkedActor::Player(spawn) => &mut spawn.common,
+            NetworkedActor::Npc(spawn) => &mut spawn.common,
+        }
+    }
+}
+
+/// Uniquely identifies an instance, as there can be more than one copy of the same zone.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash)]
+struct InstanceId(u32);
+
 #[derive(Default, Debug, Clone)]
 struct Instance {
+    /// The zone this is a copy of.
+    zone_id: u16,
+    /// Whether this instance was created for someone, instead of being shared with everyone else in the zone.
+    private: bool,
     // structure temporary, of course
     actors: HashMap<ObjectId, NetworkedActor>,
+    /// How long each battle NPC takes to come back after its corpse despawns. NPCs not in here stay dead.
+    respawn_times: HashMap<ObjectId, Duration>,
+    /// Dead NPCs, and when to despawn them.
+    corpses: Vec<(ObjectId, Instant)>,
+    /// Battle NPCs waiting to come back, along with their respawn time and when to spawn them.
+    pending_respawns: Vec<(NpcSpawn, Duration, Instant)>,
+    /// The AI of every battle NPC in this instance.
+    battle_npcs: HashMap<ObjectId, BattleNpc>,
+    /// The weather everyone in this instance currently sees.
+    weather_id: u16,
 }
 
 impl Instance {
@@ -70,351 +144,1406 @@ impl Instance {
         self.actors.insert(id, NetworkedActor::Npc(spawn));
     }
 
-    fn generate_actor_id() -> u32 {
-        // TODO: ensure we don't collide with another actor
-        fastrand::u32(..)
+    /// Same as `insert_npc`, but they come back `respawn_time` after their corpse despawns.
+    fn insert_respawning_npc(&mut self, id: ObjectId, spawn: NpcSpawn, respawn_time: Duration) {
+        self.insert_npc(id, spawn);
+        self.respawn_times.insert(id, respawn_time);
+    }
+
+    fn has_players(&self) -> bool {
+        self.actors
+            .values()
+            .any(|actor| matches!(actor, NetworkedActor::Player(_)))
     }
 }
 
 #[derive(Default, Debug, Clone)]
 struct ClientState {
-    zone_id: u16,
+    /// The name of the player's character.
+    name: String,
+    /// The instance the client is currently in, or None if they are in-between zones.
+    instance_id: Option<InstanceId>,
+    /// The instance the client was moved to, but hasn't finished loading yet.
+    pending_instance_id: Option<InstanceId>,
 }
 
-#[derive(Default, Debug)]
 struct WorldServer {
     to_remove: Vec<ClientId>,
     clients: HashMap<ClientId, (ClientHandle, ClientState)>,
-    /// Indexed by zone id
-    instances: HashMap<u16, Instance>,
+    /// Chat connections, which are linked to their zone connection through the actor id in their handle.
+    chat_clients: HashMap<ClientId, ClientHandle>,
+    instances: HashMap<InstanceId, Instance>,
+    next_instance_id: u32,
+    actor_ids: Arc<ActorIdAllocator>,
+    lua: Arc<Mutex<Lua>>,
+    /// The NPCs placed in each zone's layers, keyed by zone id.
+    zone_npcs: HashMap<u16, ZoneNpcs>,
+    /// Battle NPC scripts that were already run, keyed by their path. None if the script failed to load.
+    battle_npc_scripts: HashMap<String, Option<BattleNpcScript>>,
+    game_data: Arc<Mutex<GameData>>,
+    /// Where to reply once everyone is gone, if we're shutting down.
+    shutdown: Option<oneshot::Sender<()>>,
+    /// Actions being cast, given as the client casting them, the request and when the cast finishes.
+    pending_casts: Vec<(ClientId, ActionRequest, Instant)>,
+    /// When the world was last ticked.
+    last_tick: Instant,
+    /// When players next regenerate HP and MP.
+    next_regen: Instant,
+    /// When clients were last told to count down their status effects.
+    last_status_effect_tick: Instant,
+    /// When instances next check if their weather changed.
+    next_weather_check: Instant,
 }
 
 impl WorldServer {
-    /// Finds the instance associated with a zone, or None if it doesn't exist yet.
-    fn find_instance(&self, zone_id: u16) -> Option<&Instance> {
-        self.instances.get(&zone_id)
+    /// Creates a new instance of a zone, with the NPCs placed in its layers.
+    fn create_instance(&mut self, zone_id: u16, private: bool) -> InstanceId {
+        self.next_instance_id += 1;
+        let id = InstanceId(self.next_instance_id);
+
+        let weather_id = self.game_data.lock().unwrap().get_weather(zone_id as u32);
+
+        self.instances.insert(
+            id,
+            Instance {
+                zone_id,
+                private,
+                weather_id: weather_id.unwrap_or(1) as u16,
+                ..Default::default()
+            },
+        );
+
+        tracing::info!("Created instance {id:?} of zone {zone_id} (private: {private})");
+
+        self.populate_instance(id, zone_id);
+
+        id
+    }
+
+    /// Spawns the event NPCs and battle NPCs placed in the zone's layers into a new instance.
+    fn populate_instance(&mut self, instance_id: InstanceId, zone_id: u16) {
+        // reading the layers is slow, so it's only done the first time an instance of the zone is created
+        let ZoneNpcs {
+            event_npcs,
+            battle_npcs,
+        } = self
+            .zone_npcs
+            .entry(zone_id)
+            .or_insert_with(|| load_zone_npcs(&self.game_data, zone_id))
+            .clone();
+
+        tracing::info!(
+            "Populating instance {instance_id:?} with {} event NPC(s) and {} battle NPC(s)",
+            event_npcs.len(),
+            battle_npcs.len()
+        );
+
+        if let Some(instance) = self.instances.get_mut(&instance_id) {
+            for spawn in event_npcs {
+                let actor_id = ObjectId(self.actor_ids.allocate_npc());
+                instance.insert_npc(actor_id, spawn);
+            }
+        }
+
+        let respawn_time = Duration::from_secs(get_config().world.respawn_time);
+        for spawn in battle_npcs {
+            self.spawn_battle_npc(instance_id, spawn, respawn_time);
+        }
+    }
+
+    /// Finds the shared instance of a zone, or creates it if it doesn't exist yet.
+    fn find_shared_instance(&mut self, zone_id: u16) -> InstanceId {
+        let existing = self
+            .instances
+            .iter()
+            .find(|(_, instance)| instance.zone_id == zone_id && !instance.private)
+            .map(|(id, _)| *id);
+
+        existing.unwrap_or_else(|| self.create_instance(zone_id, false))
     }
 
-    /// Finds the instance associated with a zone, or creates it if it doesn't exist yet
-    fn find_instance_mut(&mut self, zone_id: u16) -> &mut Instance {
-        self.instances.entry(zone_id).or_default()
+    /// Finds the instance with this id, or returns None if it doesn't exist.
+    fn find_instance_mut(&mut self, instance_id: InstanceId) -> Option<&mut Instance> {
+        self.instances.get_mut(&instance_id)
     }
 
     /// Finds the instance associated with an actor, or returns None if they are not found.
     fn find_actor_instance_mut(&mut self, actor_id: u32) -> Option<&mut Instance> {
+        let instance_id = self.find_actor_instance(actor_id)?;
+        self.find_instance_mut(instance_id)
+    }
+
+    /// Finds the id of the instance associated with an actor, or returns None if they are not found.
+    fn find_actor_instance(&self, actor_id: u32) -> Option<InstanceId> {
         self.instances
-            .values_mut()
-            .find(|instance| instance.actors.contains_key(&ObjectId(actor_id)))
+            .iter()
+            .find(|(_, instance)| instance.actors.contains_key(&ObjectId(actor_id)))
+            .map(|(instance_id, _)| *instance_id)
     }
 
-    /// Tell all the clients that a new NPC spawned.
-    fn send_npc(&mut self, actor: Actor, spawn: NpcSpawn) {
-        // TODO: only send in the relevant instance
-        for (id, (handle, _)) in &mut self.clients {
+    /// Sends a message to every client currently in `instance_id`, skipping `except` if given.
+    fn send_to_instance(
+        &mut self,
+        instance_id: InstanceId,
+        except: Option<ClientId>,
+        msg: FromServer,
+    ) {
+        for (id, (handle, state)) in &mut self.clients {
             let id = *id;
 
-            let msg = FromServer::ActorSpawn(actor, spawn.clone());
+            if Some(id) == except {
+                continue;
+            }
+
+            // skip any clients not in this instance
+            if state.instance_id != Some(instance_id) {
+                continue;
+            }
 
-            if handle.send(msg).is_err() {
+            if handle.send(msg.clone()).is_err() {
                 self.to_remove.push(id);
             }
         }
     }
-}
 
-pub async fn server_main_loop(mut recv: Receiver<ToServer>) -> Result<(), std::io::Error> {
-    let data = Arc::new(Mutex::new(WorldServer::default()));
-    let game_data = Arc::new(Mutex::new(GameData::new()));
+    /// Sends a message to every client, and returns how many there were.
+    fn send_to_all(&mut self, msg: FromServer) -> usize {
+        for (id, (handle, _)) in &mut self.clients {
+            if handle.send(msg.clone()).is_err() {
+                self.to_remove.push(*id);
+            }
+        }
 
-    while let Some(msg) = recv.recv().await {
-        let mut to_remove = Vec::new();
+        self.clients.len()
+    }
 
-        match msg {
-            ToServer::NewClient(handle) => {
-                let mut data = data.lock().unwrap();
+    /// Sends a message to a single client.
+    fn send_to_client(&mut self, client_id: ClientId, msg: FromServer) {
+        if let Some((handle, _)) = self.clients.get_mut(&client_id) {
+            if handle.send(msg).is_err() {
+                self.to_remove.push(client_id);
+            }
+        }
+    }
+
+    /// Finds the zone connection of the player with this actor id, or returns None if they aren't online.
+    fn find_client_by_actor(&self, actor_id: u32) -> Option<ClientId> {
+        self.clients
+            .iter()
+            .find(|(_, (handle, _))| handle.actor_id == actor_id)
+            .map(|(id, _)| *id)
+    }
+
+    /// Finds the zone connection of the player with this name, or returns None if they aren't online.
+    fn find_client_by_name(&self, name: &str) -> Option<ClientId> {
+        self.clients
+            .iter()
+            .find(|(_, (_, state))| state.name.eq_ignore_ascii_case(name))
+            .map(|(id, _)| *id)
+    }
 
-                data.clients
-                    .insert(handle.id, (handle, ClientState::default()));
+    /// Sends a message to the zone connection of the player with this name, and returns a reply for the RCON console.
+    fn send_to_player(&mut self, name: &str, msg: FromServer, success: &str) -> String {
+        match self.find_client_by_name(name) {
+            Some(client_id) => {
+                self.send_to_client(client_id, msg);
+                success.to_string()
             }
-            ToServer::ZoneLoaded(from_id, zone_id, common_spawn) => {
-                let mut data = data.lock().unwrap();
+            None => format!("{name} is not online"),
+        }
+    }
 
-                // create a new instance if necessary
-                data.instances
-                    .entry(zone_id)
-                    .or_insert_with(Instance::default);
-
-                // Send existing player data, if any
-                if let Some(instance) = data.find_instance(zone_id).cloned() {
-                    for (id, (handle, state)) in &mut data.clients {
-                        let id = *id;
-
-                        if id == from_id {
-                            state.zone_id = zone_id;
-
-                            // send existing player data
-                            for (id, spawn) in &instance.actors {
-                                let npc_spawn = match spawn {
-                                    NetworkedActor::Player(npc_spawn) => npc_spawn,
-                                    NetworkedActor::Npc(npc_spawn) => npc_spawn,
-                                };
-
-                                // Note that we currently only support spawning via the NPC packet, hence why we don't need to differentiate here
-                                let msg = FromServer::ActorSpawn(
-                                    Actor {
-                                        id: *id,
-                                        hp: 100,
-                                        spawn_index: 0,
-                                    },
-                                    npc_spawn.clone(),
-                                );
-
-                                handle.send(msg).unwrap();
-                            }
+    /// Runs a command from the RCON console, and returns what to reply with.
+    fn run_rcon_command(&mut self, command: RconCommand) -> String {
+        match command {
+            RconCommand::ListPlayers => {
+                let mut players: Vec<String> = self
+                    .clients
+                    .values()
+                    .map(|(handle, state)| {
+                        let zone_id = state
+                            .instance_id
+                            .or(state.pending_instance_id)
+                            .and_then(|id| self.instances.get(&id))
+                            .map(|instance| instance.zone_id.to_string())
+                            .unwrap_or("none".to_string());
+
+                        format!("{} ({}) in zone {zone_id}", state.name, handle.actor_id)
+                    })
+                    .collect();
+                players.sort();
+
+                format!("{} player(s) online\n{}", players.len(), players.join("\n"))
+            }
+            RconCommand::Kick(name) => {
+                self.send_to_player(&name, FromServer::Kick, &format!("Kicked {name}"))
+            }
+            RconCommand::Broadcast(message) => {
+                let count = self.send_to_all(FromServer::Message(message));
+                format!("Sent to {count} player(s)")
+            }
+            RconCommand::Teleport(name, zone_id) => self.send_to_player(
+                &name,
+                FromServer::Teleport(zone_id),
+                &format!("Moving {name} to zone {zone_id}"),
+            ),
+            RconCommand::SetGmRank(name, rank) => self.send_to_player(
+                &name,
+                FromServer::SetGmRank(rank),
+                &format!("Set the GM rank of {name} to {rank:?}"),
+            ),
+            RconCommand::SaveAll => {
+                let count = self.send_to_all(FromServer::Save);
+                format!("Saving {count} player(s)")
+            }
+            RconCommand::ReloadScripts => {
+                // battle NPC scripts are run again the next time they're needed
+                self.battle_npc_scripts.clear();
+
+                let mut lua = self.lua.lock().unwrap();
+                match load_init_script(&mut lua) {
+                    Ok(()) => "Reloaded scripts".to_string(),
+                    Err(err) => {
+                        tracing::warn!("Failed to load Init.lua: {:?}", err);
+                        format!("Failed to reload scripts: {err}")
+                    }
+                }
+            }
+        }
+    }
 
-                            break;
+    /// Applies the effects of an action someone used, and tells everyone close enough to see it what happened.
+    fn execute_action(
+        &mut self,
+        from_actor_id: u32,
+        request: ActionRequest,
+        mut effects_builder: EffectsBuilder,
+    ) {
+        // the rest couldn't be shown to anyone, so they aren't applied either
+        if effects_builder.effects.len() > MAX_ACTION_EFFECTS {
+            tracing::warn!(
+                "Action {} has {} effects, only the first {MAX_ACTION_EFFECTS} are used!",
+                request.action_key,
+                effects_builder.effects.len()
+            );
+            effects_builder.effects.truncate(MAX_ACTION_EFFECTS);
+        }
+
+        let Some(instance_id) = self.find_actor_instance(from_actor_id) else {
+            return;
+        };
+        let Some(instance) = self.find_instance_mut(instance_id) else {
+            return;
+        };
+
+        let Some(caster) = instance.find_actor(ObjectId(from_actor_id)) else {
+            return;
+        };
+        let caster_position = caster.common().pos;
+        let caster_rotation = caster.common().rotation;
+
+        // the dead can't do anything
+        if caster.common().mode == CharacterMode::Dead {
+            return;
+        }
+
+        let target_id = request.target.object_id;
+        let mut target_is_player = false;
+        let mut target_hp_mp = None;
+        let mut target_died = false;
+        let mut hostile = false;
+        let mut damage_dealt = 0;
+        if let Some(target) = instance.find_actor_mut(target_id) {
+            target_is_player = matches!(target, NetworkedActor::Player(_));
+
+            let common = target.common_mut();
+            let was_alive = common.mode != CharacterMode::Dead;
+
+            // the dead can't be hurt or healed, as raising isn't supported yet
+            if was_alive {
+                for effect in &effects_builder.effects {
+                    match effect.kind {
+                        EffectKind::Damage { amount, .. } => {
+                            common.hp_curr = common.hp_curr.saturating_sub(amount as u32);
+                            damage_dealt += amount as u32;
+                            hostile = true;
+                        }
+                        EffectKind::Heal { amount, .. } => {
+                            common.hp_curr = (common.hp_curr + amount as u32).min(common.hp_max);
                         }
+                        EffectKind::Miss => hostile = true,
+                        // status effects are tracked by the connection they're given to
+                        EffectKind::GainEffect { .. }
+                        | EffectKind::Nothing
+                        | EffectKind::BeginCombo => {}
                     }
                 }
+            }
 
-                let (client, _) = data.clients.get(&from_id).unwrap().clone();
+            target_hp_mp = Some((common.hp_curr, common.mp_curr));
+            target_died = was_alive && common.hp_curr == 0;
+        }
 
-                // add the connection's actor to the table
-                {
-                    let instance = data.find_instance_mut(zone_id);
-                    instance.actors.insert(
-                        ObjectId(client.actor_id),
-                        NetworkedActor::Player(NpcSpawn {
-                            common: common_spawn.clone(),
-                            ..Default::default()
-                        }),
-                    );
+        // battle NPCs fight back against whoever attacked them, even if it missed
+        if hostile && target_id.0 != from_actor_id {
+            if let Some(battle_npc) = instance.battle_npcs.get_mut(&target_id) {
+                battle_npc.add_hate(ObjectId(from_actor_id), damage_dealt.max(1));
+            }
+        }
+
+        // everyone close enough to see it, including the caster
+        let recipients: Vec<u32> = instance
+            .actors
+            .iter()
+            .filter_map(|(id, actor)| {
+                let NetworkedActor::Player(spawn) = actor else {
+                    return None;
+                };
+
+                if spawn.common.pos.distance(&caster_position) > ACTION_RANGE {
+                    return None;
                 }
 
-                // Then tell any clients in the zone that we spawned
-                for (id, (handle, state)) in &mut data.clients {
-                    let id = *id;
+                Some(id.0)
+            })
+            .collect();
+
+        let mut effects = [ActionEffect::default(); MAX_ACTION_EFFECTS];
+        effects[..effects_builder.effects.len()].copy_from_slice(&effects_builder.effects);
+
+        let action_result = ActionResult {
+            main_target: request.target,
+            target_id_again: request.target,
+            action_id: request.action_key,
+            animation_lock_time: 0.6,
+            rotation: caster_rotation,
+            action_animation_id: request.action_key as u16, // assuming action id == animation id
+            flag: 1,
+            effect_count: effects_builder.effects.len() as u8,
+            effects,
+            unk1: 2662353,
+            unk2: 3758096384,
+            hidden_animation: 1,
+            ..Default::default()
+        };
+
+        for actor_id in recipients {
+            let Some(client_id) = self.find_client_by_actor(actor_id) else {
+                continue;
+            };
+
+            self.send_to_client(
+                client_id,
+                FromServer::ActionResult(from_actor_id, action_result.clone()),
+            );
+
+            if let Some((hp, mp)) = target_hp_mp {
+                self.send_to_client(client_id, FromServer::UpdateHpMp(target_id.0, hp, mp));
+            }
+        }
 
-                    // don't bother telling the client who told us
-                    if id == from_id {
-                        continue;
-                    }
+        for effect in &effects_builder.effects {
+            let EffectKind::GainEffect { effect_id, .. } = effect.kind else {
+                continue;
+            };
 
-                    // skip any clients not in our zone
-                    if state.zone_id != zone_id {
-                        continue;
+            if !target_is_player {
+                tracing::warn!(
+                    "Status effects on NPCs aren't supported yet, ignoring {effect_id}!"
+                );
+                continue;
+            }
+
+            let duration = effects_builder
+                .effect_durations
+                .get(&effect_id)
+                .copied()
+                .unwrap_or_default();
+
+            if let Some(client_id) = self.find_client_by_actor(target_id.0) {
+                self.send_to_client(
+                    client_id,
+                    FromServer::GainEffect(effect_id, duration, from_actor_id),
+                );
+            }
+        }
+
+        if target_died {
+            self.kill_actor(instance_id, target_id);
+        }
+    }
+
+    /// Marks an actor as dead, and tells everyone in the instance. NPCs are despawned after a while, but players stay knocked out until they return to their home point.
+    fn kill_actor(&mut self, instance_id: InstanceId, actor_id: ObjectId) {
+        let Some(instance) = self.find_instance_mut(instance_id) else {
+            return;
+        };
+
+        let Some(actor) = instance.find_actor_mut(actor_id) else {
+            return;
+        };
+
+        let common = actor.common_mut();
+        common.hp_curr = 0;
+        common.mode = CharacterMode::Dead;
+
+        if let NetworkedActor::Npc(_) = actor {
+            instance
+                .corpses
+                .push((actor_id, Instant::now() + CORPSE_DESPAWN_TIME));
+        }
+
+        tracing::info!("{} died in {instance_id:?}", actor_id.0);
+
+        let msg = FromServer::ActorControl(
+            actor_id.0,
+            ActorControl {
+                category: ActorControlCategory::Kill { animation_id: 0 },
+            },
+        );
+        self.send_to_instance(instance_id, None, msg);
+
+        let msg = FromServer::ActorControl(
+            actor_id.0,
+            ActorControl {
+                category: ActorControlCategory::SetMode {
+                    mode: CharacterMode::Dead,
+                    mode_arg: 0,
+                },
+            },
+        );
+        self.send_to_instance(instance_id, None, msg);
+    }
+
+    /// Despawns the corpses and respawns the battle NPCs whose time has come.
+    fn process_respawns(&mut self) {
+        let now = Instant::now();
+
+        let mut despawns = Vec::new();
+        let mut respawns = Vec::new();
+        for (instance_id, instance) in &mut self.instances {
+            instance.corpses.retain(|(actor_id, despawn_at)| {
+                if *despawn_at > now {
+                    return true;
+                }
+
+                despawns.push((*instance_id, *actor_id));
+                false
+            });
+
+            instance
+                .pending_respawns
+                .retain(|(spawn, respawn_time, respawn_at)| {
+                    if *respawn_at > now {
+                        return true;
                     }
 
-                    let msg = FromServer::ActorSpawn(
-                        Actor {
-                            id: ObjectId(client.actor_id),
-                            hp: 0,
-                            spawn_index: 0,
-                        },
-                        NpcSpawn {
-                            common: common_spawn.clone(),
-                            ..Default::default()
-                        },
-                    );
+                    respawns.push((*instance_id, spawn.clone(), *respawn_time));
+                    false
+                });
+        }
 
-                    if handle.send(msg).is_err() {
-                        to_remove.push(id);
+        for (instance_id, actor_id) in despawns {
+            // remember how to bring them back before they're gone
+            if let Some(instance) = self.find_instance_mut(instance_id) {
+                if let (Some(respawn_time), Some(NetworkedActor::Npc(spawn))) = (
+                    instance.respawn_times.get(&actor_id).copied(),
+                    instance.find_actor(actor_id),
+                ) {
+                    let mut spawn = spawn.clone();
+                    spawn.common.hp_curr = spawn.common.hp_max;
+                    spawn.common.mp_curr = spawn.common.mp_max;
+                    spawn.common.mode = CharacterMode::Normal;
+
+                    // they died wherever they were chasing someone, so put them back where they started
+                    if let Some(battle_npc) = instance.battle_npcs.get(&actor_id) {
+                        spawn.common.pos = battle_npc.home_position;
+                        spawn.common.rotation = battle_npc.home_rotation;
                     }
+
+                    instance
+                        .pending_respawns
+                        .push((spawn, respawn_time, now + respawn_time));
                 }
             }
-            ToServer::LeftZone(from_id, actor_id, zone_id) => {
-                let mut data = data.lock().unwrap();
 
-                // when the actor leaves the zone, remove them from the instance
-                let current_instance = data.find_actor_instance_mut(actor_id).unwrap();
-                current_instance.actors.remove(&ObjectId(actor_id));
+            self.remove_actor(None, actor_id.0);
+        }
 
-                // Then tell any clients in the zone that we left
-                for (id, (handle, state)) in &mut data.clients {
-                    let id = *id;
+        for (instance_id, spawn, respawn_time) in respawns {
+            self.spawn_battle_npc(instance_id, spawn, respawn_time);
+        }
+    }
 
-                    // don't bother telling the client who told us
-                    if id == from_id {
-                        continue;
+    /// Spawns a battle NPC into an instance, which comes back `respawn_time` after its corpse despawns.
+    fn spawn_battle_npc(
+        &mut self,
+        instance_id: InstanceId,
+        spawn: NpcSpawn,
+        respawn_time: Duration,
+    ) {
+        // the instance may have been destroyed in the meantime
+        if !self.instances.contains_key(&instance_id) {
+            return;
+        }
+
+        // let the script registered for its BNpcBase decide how it behaves
+        let mut battle_npc = BattleNpc::new(spawn.common.pos, spawn.common.rotation, None);
+        {
+            let lua = self.lua.lock().unwrap();
+            let script = lua
+                .app_data_ref::<ExtraLuaState>()
+                .unwrap()
+                .battle_npc_scripts
+                .get(&spawn.common.bnpc_base)
+                .cloned();
+
+            if let Some(script) = script {
+                let loaded = self
+                    .battle_npc_scripts
+                    .entry(script.clone())
+                    .or_insert_with(|| BattleNpcScript::load(&lua, &script));
+                if let Some(loaded) = loaded {
+                    battle_npc.behaviour = loaded.behaviour;
+                }
+                battle_npc.script = Some(script);
+            }
+        }
+
+        // passive ones only fight back, no matter what their script says
+        if spawn.aggression_mode == PASSIVE_AGGRESSION_MODE {
+            battle_npc.behaviour.aggro_range = 0.0;
+        }
+
+        let actor_id = ObjectId(self.actor_ids.allocate_npc());
+        if let Some(instance) = self.find_instance_mut(instance_id) {
+            instance.insert_respawning_npc(actor_id, spawn.clone(), respawn_time);
+            instance.battle_npcs.insert(actor_id, battle_npc);
+        }
+
+        self.send_npc(
+            instance_id,
+            Actor {
+                id: actor_id,
+                hp: spawn.common.hp_curr,
+                ..Default::default()
+            },
+            spawn,
+        );
+    }
+
+    /// Lets every battle NPC pick a target, chase them, attack them or walk back to where it spawned.
+    /// `elapsed` is how many seconds passed since they last moved.
+    fn update_battle_npcs(&mut self, elapsed: f32) {
+        let now = Instant::now();
+
+        let mut moves = Vec::new();
+        let mut heals = Vec::new();
+        let mut attacks = Vec::new();
+        for (instance_id, instance) in &mut self.instances {
+            // everyone who can be attacked, and where they are
+            let players: Vec<(ObjectId, Position)> = instance
+                .actors
+                .iter()
+                .filter_map(|(id, actor)| match actor {
+                    NetworkedActor::Player(spawn) if spawn.common.mode != CharacterMode::Dead => {
+                        Some((*id, spawn.common.pos))
                     }
+                    _ => None,
+                })
+                .collect();
 
-                    // skip any clients not in our zone
-                    if state.zone_id != zone_id {
-                        continue;
+            for (actor_id, battle_npc) in &mut instance.battle_npcs {
+                let Some(NetworkedActor::Npc(spawn)) = instance.actors.get_mut(actor_id) else {
+                    continue;
+                };
+                let common = &mut spawn.common;
+
+                if common.mode == CharacterMode::Dead {
+                    continue;
+                }
+
+                // forget about anyone who died or left
+                battle_npc
+                    .hate
+                    .retain(|id, _| players.iter().any(|(player_id, _)| player_id == id));
+
+                if battle_npc.state == BattleNpcState::Idle
+                    && battle_npc.behaviour.aggro_range > 0.0
+                {
+                    let closest = players
+                        .iter()
+                        .map(|(player_id, pos)| (player_id, pos.distance(&common.pos)))
+                        .filter(|(_, distance)| *distance <= battle_npc.behaviour.aggro_range)
+                        .min_by(|(_, a), (_, b)| a.total_cmp(b));
+
+                    if let Some((player_id, _)) = closest {
+                        battle_npc.add_hate(*player_id, 1);
                     }
+                }
 
-                    let msg = FromServer::ActorDespawn(actor_id);
+                match battle_npc.state {
+                    BattleNpcState::Idle => {}
+                    BattleNpcState::Engaged => {
+                        let target = battle_npc.top_hate().and_then(|target_id| {
+                            players
+                                .iter()
+                                .find(|(player_id, _)| *player_id == target_id)
+                        });
+
+                        let Some((target_id, target_position)) = target else {
+                            // nobody left to fight
+                            battle_npc.leash();
+                            continue;
+                        };
 
-                    if handle.send(msg).is_err() {
-                        to_remove.push(id);
+                        if common.pos.distance(&battle_npc.home_position)
+                            > battle_npc.behaviour.leash_range
+                        {
+                            battle_npc.leash();
+                        } else if common.pos.distance(target_position)
+                            > battle_npc.behaviour.attack_range
+                        {
+                            (common.pos, common.rotation) = step_towards(
+                                common.pos,
+                                *target_position,
+                                battle_npc.behaviour.speed * elapsed,
+                                battle_npc.behaviour.attack_range,
+                            );
+                            moves.push((*instance_id, *actor_id, common.pos, common.rotation));
+                        } else if now >= battle_npc.next_auto_attack {
+                            battle_npc.next_auto_attack = now + battle_npc.auto_attack_interval();
+                            attacks.push((
+                                *actor_id,
+                                *target_id,
+                                battle_npc.script.clone(),
+                                battle_npc.behaviour.auto_attack_damage,
+                            ));
+                        }
+                    }
+                    BattleNpcState::Leashing => {
+                        (common.pos, common.rotation) = step_towards(
+                            common.pos,
+                            battle_npc.home_position,
+                            battle_npc.behaviour.speed * elapsed,
+                            0.0,
+                        );
+
+                        // once they're back, they're good as new
+                        if common.pos.distance(&battle_npc.home_position) < 0.1 {
+                            common.rotation = battle_npc.home_rotation;
+                            common.hp_curr = common.hp_max;
+                            battle_npc.state = BattleNpcState::Idle;
+                            heals.push((*instance_id, *actor_id, common.hp_curr, common.mp_curr));
+                        }
+
+                        moves.push((*instance_id, *actor_id, common.pos, common.rotation));
                     }
                 }
             }
-            ToServer::Message(from_id, msg) => {
-                let mut data = data.lock().unwrap();
+        }
 
-                for (id, (handle, _)) in &mut data.clients {
-                    let id = *id;
+        for (instance_id, actor_id, position, rotation) in moves {
+            let msg = FromServer::ActorMove(actor_id.0, position, rotation);
+            self.send_to_instance(instance_id, None, msg);
+        }
 
-                    if id == from_id {
-                        continue;
-                    }
+        for (instance_id, actor_id, hp, mp) in heals {
+            let msg = FromServer::UpdateHpMp(actor_id.0, hp, mp);
+            self.send_to_instance(instance_id, None, msg);
+        }
 
-                    let msg = FromServer::Message(msg.clone());
+        for (actor_id, target_id, script, damage) in attacks {
+            let effects_builder = script
+                .and_then(|script| {
+                    let lua = self.lua.lock().unwrap();
+                    self.battle_npc_scripts
+                        .entry(script.clone())
+                        .or_insert_with(|| BattleNpcScript::load(&lua, &script))
+                        .as_ref()?
+                        .run_auto_attack(&script)
+                })
+                .unwrap_or_else(|| default_auto_attack(damage));
+
+            let request = ActionRequest {
+                action_key: AUTO_ATTACK_ACTION_ID,
+                target: ObjectTypeId {
+                    object_id: target_id,
+                    object_type: 0,
+                },
+                ..Default::default()
+            };
+
+            self.execute_action(actor_id.0, request, effects_builder);
+        }
+    }
 
-                    if handle.send(msg).is_err() {
-                        to_remove.push(id);
-                    }
+    /// Advances the world by one tick, and logs if it took longer than a tick should.
+    fn tick(&mut self) {
+        let now = Instant::now();
+        let elapsed = now - self.last_tick;
+        self.last_tick = now;
+
+        self.process_casts(now);
+        self.update_battle_npcs(elapsed.as_secs_f32());
+        self.process_respawns();
+
+        if now >= self.next_regen {
+            self.next_regen = now + REGEN_INTERVAL;
+            self.regenerate();
+        }
+
+        let status_effect_elapsed = now - self.last_status_effect_tick;
+        if status_effect_elapsed >= STATUS_EFFECT_TICK_INTERVAL {
+            self.last_status_effect_tick = now;
+            self.send_to_all(FromServer::TickStatusEffects(
+                status_effect_elapsed.as_secs_f32(),
+            ));
+        }
+
+        if now >= self.next_weather_check {
+            self.next_weather_check = now + WEATHER_CHECK_INTERVAL;
+            self.update_weather();
+        }
+
+        let took = now.elapsed();
+        if took > WORLD_TICK_INTERVAL {
+            tracing::warn!(
+                "World tick took {took:?}, which is over the {WORLD_TICK_INTERVAL:?} budget!"
+            );
+        }
+    }
+
+    /// Finishes the casts whose time has come.
+    fn process_casts(&mut self, now: Instant) {
+        let mut finished = Vec::new();
+        self.pending_casts
+            .retain(|(client_id, request, finish_at)| {
+                if *finish_at > now {
+                    return true;
                 }
+
+                finished.push((*client_id, request.clone()));
+                false
+            });
+
+        for (client_id, request) in finished {
+            self.send_to_client(client_id, FromServer::ActionComplete(request));
+        }
+    }
+
+    /// Gives living players back some of their HP and MP, less while something is fighting them.
+    fn regenerate(&mut self) {
+        let mut updates = Vec::new();
+        for (instance_id, instance) in &mut self.instances {
+            for (actor_id, actor) in &mut instance.actors {
+                let NetworkedActor::Player(spawn) = actor else {
+                    continue;
+                };
+                let common = &mut spawn.common;
+
+                if common.mode == CharacterMode::Dead
+                    || (common.hp_curr >= common.hp_max && common.mp_curr >= common.mp_max)
+                {
+                    continue;
+                }
+
+                let in_combat = instance
+                    .battle_npcs
+                    .values()
+                    .any(|battle_npc| battle_npc.hate.contains_key(actor_id));
+
+                if !in_combat {
+                    common.hp_curr =
+                        (common.hp_curr + common.hp_max * REGEN_PERCENT / 100).min(common.hp_max);
+                }
+
+                let mp_percent = if in_combat {
+                    REGEN_PERCENT_IN_COMBAT
+                } else {
+                    REGEN_PERCENT
+                };
+                let mp_gained = (common.mp_max as u32 * mp_percent / 100) as u16;
+                common.mp_curr = common.mp_curr.saturating_add(mp_gained).min(common.mp_max);
+
+                updates.push((*instance_id, *actor_id, common.hp_curr, common.mp_curr));
             }
-            ToServer::ActorMoved(from_id, actor_id, position, rotation) => {
+        }
+
+        for (instance_id, actor_id, hp, mp) in updates {
+            let msg = FromServer::UpdateHpMp(actor_id.0, hp, mp);
+            self.send_to_instance(instance_id, None, msg);
+        }
+    }
+
+    /// Tells every instance whose weather changed about it.
+    fn update_weather(&mut self) {
+        let mut changes = Vec::new();
+        {
+            let mut game_data = self.game_data.lock().unwrap();
+            for (instance_id, instance) in &mut self.instances {
+                let Some(weather_id) = game_data.get_weather(instance.zone_id as u32) else {
+                    continue;
+                };
+
+                if weather_id as u16 != instance.weather_id {
+                    instance.weather_id = weather_id as u16;
+                    changes.push((*instance_id, instance.weather_id));
+                }
+            }
+        }
+
+        for (instance_id, weather_id) in changes {
+            tracing::info!("Weather in {instance_id:?} changed to {weather_id}");
+            self.send_to_instance(instance_id, None, FromServer::ChangeWeather(weather_id));
+        }
+    }
+
+    /// Removes any clients that errored out, and finishes shutting down once everyone is gone.
+    fn remove_dead_clients(&mut self) {
+        // sending despawns may discover even more dead clients, so keep going until there's none left
+        while let Some(remove_id) = self.to_remove.pop() {
+            // remove any actors they had
+            self.chat_clients.remove(&remove_id);
+
+            if let Some((handle, state)) = self.clients.remove(&remove_id) {
+                self.remove_actor(None, handle.actor_id);
+
+                // don't leave behind an instance they never finished loading into
+                if let Some(pending_instance_id) = state.pending_instance_id {
+                    self.cleanup_instance(pending_instance_id);
+                }
+            }
+        }
+
+        // everyone has saved and left, so it's safe to stop now
+        if self.clients.is_empty() {
+            if let Some(reply) = self.shutdown.take() {
+                let _ = reply.send(());
+            }
+        }
+    }
+
+    /// Tell all the clients in the instance that a new NPC spawned.
+    fn send_npc(&mut self, instance_id: InstanceId, actor: Actor, spawn: NpcSpawn) {
+        self.send_to_instance(instance_id, None, FromServer::ActorSpawn(actor, spawn));
+    }
+
+    /// Removes an actor from whatever instance they're in, and tells the clients in that instance they're gone.
+    fn remove_actor(&mut self, from_id: Option<ClientId>, actor_id: u32) {
+        let Some(instance_id) = self.find_actor_instance(actor_id) else {
+            return;
+        };
+
+        if let Some(instance) = self.find_instance_mut(instance_id) {
+            instance.respawn_times.remove(&ObjectId(actor_id));
+            instance.corpses.retain(|(id, _)| id.0 != actor_id);
+            instance.battle_npcs.remove(&ObjectId(actor_id));
+
+            // player ids belong to their character, so only NPC ids are given back
+            if let Some(NetworkedActor::Npc(_)) = instance.actors.remove(&ObjectId(actor_id)) {
+                self.actor_ids.release(actor_id);
+            }
+        }
+
+        self.send_to_instance(instance_id, from_id, FromServer::ActorDespawn(actor_id));
+        self.cleanup_instance(instance_id);
+    }
+
+    /// Destroys a private instance once nobody is in it, or on their way to it.
+    fn cleanup_instance(&mut self, instance_id: InstanceId) {
+        let Some(instance) = self.instances.get(&instance_id) else {
+            return;
+        };
+
+        if !instance.private || instance.has_players() {
+            return;
+        }
+
+        let still_loading = self
+            .clients
+            .values()
+            .any(|(_, state)| state.pending_instance_id == Some(instance_id));
+        if still_loading {
+            return;
+        }
+
+        tracing::info!(
+            "Destroying instance {instance_id:?} of zone {}",
+            instance.zone_id
+        );
+
+        if let Some(instance) = self.instances.remove(&instance_id) {
+            for (id, actor) in instance.actors {
+                if let NetworkedActor::Npc(_) = actor {
+                    self.actor_ids.release(id.0);
+                }
+            }
+        }
+    }
+}
+
+/// The NPCs placed in a zone's layers, ready to be spawned into its instances.
+#[derive(Clone, Default)]
+struct ZoneNpcs {
+    event_npcs: Vec<NpcSpawn>,
+    battle_npcs: Vec<NpcSpawn>,
+}
+
+/// Reads the event NPCs and battle NPCs placed in a zone's layers.
+fn load_zone_npcs(game_data: &Mutex<GameData>, zone_id: u16) -> ZoneNpcs {
+    let mut game_data = game_data.lock().unwrap();
+    let zone = Zone::load(&mut game_data, zone_id);
+
+    let mut npcs = ZoneNpcs::default();
+
+    // TODO: aetherytes and other event objects need their own spawn packet, which we don't support yet
+    for (object, enpc) in zone.event_npcs() {
+        let base_id = enpc.parent_data.parent_data.base_id;
+
+        npcs.event_npcs.push(NpcSpawn {
+            common: CommonSpawn {
+                hp_curr: 100,
+                hp_max: 100,
+                bnpc_base: base_id,
+                object_kind: ObjectKind::EventNpc,
+                model_chara: game_data.get_enpc_model_chara(base_id).unwrap_or_default(),
+                pos: Position {
+                    x: object.transform.translation[0],
+                    y: object.transform.translation[1],
+                    z: object.transform.translation[2],
+                },
+                rotation: object.transform.rotation[1],
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+    }
+
+    for (object, bnpc) in zone.battle_npcs() {
+        let base_id = bnpc.parent_data.parent_data.base_id;
+        let level = bnpc.level.clamp(1, u8::MAX as u16) as u8;
+        let hp = estimate_battle_npc_hp(level);
+
+        npcs.battle_npcs.push(NpcSpawn {
+            // the layer decides if it's passive or aggressive
+            aggression_mode: bnpc.active_type,
+            common: CommonSpawn {
+                hp_curr: hp,
+                hp_max: hp,
+                mp_curr: 100,
+                mp_max: 100,
+                bnpc_base: base_id,
+                bnpc_name: bnpc.name_id,
+                object_kind: ObjectKind::BattleNpc(BattleNpcSubKind::Enemy),
+                level,
+                battalion: 4,
+                model_chara: game_data.get_bnpc_model_chara(base_id).unwrap_or_default(),
+                pos: Position {
+                    x: object.transform.translation[0],
+                    y: object.transform.translation[1],
+                    z: object.transform.translation[2],
+                },
+                rotation: object.transform.rotation[1],
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+    }
+
+    npcs
+}
+
+/// Formats a message someone sent in a channel the way the chat log shows it.
+fn format_chat_message(channel: ChatChannel, sender_name: &str, message: &str) -> String {
+    match channel {
+        ChatChannel::Shout => format!("{sender_name} shouts: {message}"),
+        ChatChannel::Yell => format!("{sender_name} yells: {message}"),
+        ChatChannel::CustomEmote | ChatChannel::StandardEmote => {
+            format!("{sender_name} {message}")
+        }
+        _ => format!("{sender_name}: {message}"),
+    }
+}
+
+/// The error shown to someone who tried to talk in a channel the server can't deliver to yet.
+fn unsupported_channel_message(channel: ChatChannel) -> String {
+    let name = match channel {
+        ChatChannel::Tell => {
+            return "Tells aren't available on this server yet, your message wasn't sent."
+                .to_string();
+        }
+        ChatChannel::Party => "Party chat".to_string(),
+        ChatChannel::Alliance => "Alliance chat".to_string(),
+        ChatChannel::FreeCompany => "Free Company chat".to_string(),
+        ChatChannel::NoviceNetwork => "The Novice Network".to_string(),
+        _ => match channel.linkshell_index() {
+            Some(index) => format!("Linkshell {} chat", index + 1),
+            None => format!("{channel:?} chat"),
+        },
+    };
+
+    format!("{name} isn't available on this server yet, your message wasn't sent.")
+}
+
+pub async fn server_main_loop(
+    mut recv: Receiver<ToServer>,
+    actor_ids: Arc<ActorIdAllocator>,
+    lua: Arc<Mutex<Lua>>,
+) -> Result<(), std::io::Error> {
+    let game_data = Arc::new(Mutex::new(GameData::new()));
+    let data = Arc::new(Mutex::new(WorldServer {
+        to_remove: Vec::new(),
+        clients: HashMap::new(),
+        chat_clients: HashMap::new(),
+        instances: HashMap::new(),
+        next_instance_id: 0,
+        actor_ids,
+        lua,
+        zone_npcs: HashMap::new(),
+        battle_npc_scripts: HashMap::new(),
+        game_data: game_data.clone(),
+        shutdown: None,
+        pending_casts: Vec::new(),
+        last_tick: Instant::now(),
+        next_regen: Instant::now() + REGEN_INTERVAL,
+        last_status_effect_tick: Instant::now(),
+        next_weather_check: Instant::now() + WEATHER_CHECK_INTERVAL,
+    }));
+
+    // periodically ask everyone to save, so a crash doesn't lose too much progress
+    let autosave_interval = get_config().world.autosave_interval;
+    if autosave_interval > 0 {
+        let data = data.clone();
+        tokio::task::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(autosave_interval));
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+
+                let mut data = data.lock().unwrap();
+                tracing::info!("Autosaving {} player(s)...", data.clients.len());
+                data.send_to_all(FromServer::Save);
+            }
+        });
+    }
+
+    // if we fall behind, don't try to catch up with a burst of ticks
+    let mut tick_interval = tokio::time::interval(WORLD_TICK_INTERVAL);
+    tick_interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+
+    loop {
+        let msg = tokio::select! {
+            _ = tick_interval.tick() => {
                 let mut data = data.lock().unwrap();
+                data.tick();
+                data.remove_dead_clients();
+                continue;
+            }
+            msg = recv.recv() => msg,
+        };
 
-                if let Some(instance) = data.find_actor_instance_mut(actor_id) {
-                    if let Some((_, spawn)) = instance
-                        .actors
-                        .iter_mut()
-                        .find(|actor| *actor.0 == ObjectId(actor_id))
-                    {
-                        let common = match spawn {
-                            NetworkedActor::Player(npc_spawn) => &mut npc_spawn.common,
-                            NetworkedActor::Npc(npc_spawn) => &mut npc_spawn.common,
-                        };
-                        common.pos = position;
-                        common.rotation = rotation;
-                    }
+        let Some(msg) = msg else {
+            break;
+        };
 
-                    for (id, (handle, _)) in &mut data.clients {
-                        let id = *id;
+        match msg {
+            ToServer::NewClient(handle, name) => {
+                let mut data = data.lock().unwrap();
 
-                        if id == from_id {
-                            continue;
-                        }
+                data.clients.insert(
+                    handle.id,
+                    (
+                        handle,
+                        ClientState {
+                            name,
+                            ..Default::default()
+                        },
+                    ),
+                );
+            }
+            ToServer::NewChatClient(handle) => {
+                let mut data = data.lock().unwrap();
 
-                        let msg = FromServer::ActorMove(actor_id, position, rotation);
+                data.chat_clients.insert(handle.id, handle);
+            }
+            ToServer::ChangeZone(from_id, actor_id, zone_id, request) => {
+                let mut data = data.lock().unwrap();
 
-                        if handle.send(msg).is_err() {
-                            to_remove.push(id);
-                        }
-                    }
+                if !data.clients.contains_key(&from_id) {
+                    continue;
                 }
+
+                // find (or create) the instance they're moving into
+                let instance_id = match request {
+                    InstanceRequest::Shared => data.find_shared_instance(zone_id),
+                    InstanceRequest::Private => data.create_instance(zone_id, true),
+                };
+
+                let Some((_, state)) = data.clients.get_mut(&from_id) else {
+                    continue;
+                };
+
+                // they won't recieve anything from either instance until they finish loading
+                state.instance_id = None;
+                let previous_pending_id = state.pending_instance_id.replace(instance_id);
+
+                // remove them from their old instance, and tell everyone there they left
+                data.remove_actor(Some(from_id), actor_id);
+
+                // they may have been moved again before finishing loading
+                if let Some(previous_pending_id) = previous_pending_id {
+                    data.cleanup_instance(previous_pending_id);
+                }
+
+                tracing::info!(
+                    "Moving client {from_id:?} to instance {instance_id:?} of zone {zone_id}"
+                );
+
+                data.send_to_client(from_id, FromServer::ChangeZone(zone_id));
             }
-            ToServer::ClientTrigger(from_id, from_actor_id, trigger) => {
+            ToServer::ZoneLoaded(from_id, common_spawn) => {
                 let mut data = data.lock().unwrap();
 
-                for (id, (handle, _)) in &mut data.clients {
-                    let id = *id;
+                let Some((client, state)) = data.clients.get_mut(&from_id) else {
+                    continue;
+                };
 
-                    tracing::info!("{:#?}", trigger);
+                let Some(instance_id) = state.pending_instance_id.take() else {
+                    tracing::warn!(
+                        "Client {from_id:?} finished loading a zone it was never moved to, ignoring!"
+                    );
+                    continue;
+                };
 
-                    // handle player-to-server actions
-                    if id == from_id {
-                        if let ClientTriggerCommand::TeleportQuery { aetheryte_id } =
-                            &trigger.trigger
-                        {
-                            let msg = FromServer::ActorControlSelf(ActorControlSelf {
-                                category: ActorControlCategory::TeleportStart {
-                                    insufficient_gil: 0,
-                                    aetheryte_id: *aetheryte_id,
-                                },
-                            });
-
-                            if handle.send(msg).is_err() {
-                                to_remove.push(id);
-                            }
-                        }
+                state.instance_id = Some(instance_id);
+                let actor_id = client.actor_id;
+
+                let Some(instance) = data.find_instance_mut(instance_id).cloned() else {
+                    tracing::warn!(
+                        "Client {from_id:?} loaded into instance {instance_id:?}, which no longer exists!"
+                    );
+                    continue;
+                };
+
+                // send existing actors in the instance
+                for (id, spawn) in &instance.actors {
+                    let npc_spawn = match spawn {
+                        NetworkedActor::Player(npc_spawn) => npc_spawn,
+                        NetworkedActor::Npc(npc_spawn) => npc_spawn,
+                    };
+
+                    // Note that we currently only support spawning via the NPC packet, hence why we don't need to differentiate here
+                    let msg = FromServer::ActorSpawn(
+                        Actor {
+                            id: *id,
+                            hp: npc_spawn.common.hp_curr,
+                            spawn_index: 0,
+                        },
+                        npc_spawn.clone(),
+                    );
+
+                    data.send_to_client(from_id, msg);
+                }
+
+                // add the connection's actor to the table
+                if let Some(instance) = data.find_instance_mut(instance_id) {
+                    instance.actors.insert(
+                        ObjectId(actor_id),
+                        NetworkedActor::Player(NpcSpawn {
+                            common: common_spawn.clone(),
+                            ..Default::default()
+                        }),
+                    );
+                }
+
+                // Then tell any clients in the instance that we spawned
+                let msg = FromServer::ActorSpawn(
+                    Actor {
+                        id: ObjectId(actor_id),
+                        hp: common_spawn.hp_curr,
+                        spawn_index: 0,
+                    },
+                    NpcSpawn {
+                        common: common_spawn,
+                        ..Default::default()
+                    },
+                );
+
+                data.send_to_instance(instance_id, Some(from_id), msg);
+            }
+            ToServer::Message(from_id, from_actor_id, channel, message) => {
+                let mut data = data.lock().unwrap();
+
+                let range = match channel {
+                    ChatChannel::Say | ChatChannel::CustomEmote | ChatChannel::StandardEmote => {
+                        Some(SAY_RANGE)
+                    }
+                    ChatChannel::Yell => Some(YELL_RANGE),
+                    ChatChannel::Shout => None,
+                    _ => {
+                        // TODO: party, linkshell, etc. need those groups to exist on the server first
+                        let msg = FromServer::Message(unsupported_channel_message(channel));
+                        data.send_to_client(from_id, msg);
                         continue;
                     }
+                };
 
-                    match &trigger.trigger {
-                        ClientTriggerCommand::SetTarget { actor_id } => {
-                            let msg = FromServer::ActorControlTarget(
-                                from_actor_id,
-                                ActorControlTarget {
-                                    category: ActorControlCategory::SetTarget {
-                                        actor_id: *actor_id,
-                                    },
-                                },
-                            );
+                let Some(sender_name) = data
+                    .clients
+                    .get(&from_id)
+                    .map(|(_, state)| state.name.clone())
+                else {
+                    continue;
+                };
 
-                            if handle.send(msg).is_err() {
-                                to_remove.push(id);
-                            }
-                        }
-                        ClientTriggerCommand::ChangePose { unk1, pose } => {
-                            let msg = FromServer::ActorControl(
-                                from_actor_id,
-                                ActorControl {
-                                    category: ActorControlCategory::Pose {
-                                        unk1: *unk1,
-                                        pose: *pose,
-                                    },
-                                },
-                            );
+                let Some(instance) = data.find_actor_instance_mut(from_actor_id) else {
+                    continue;
+                };
 
-                            if handle.send(msg).is_err() {
-                                to_remove.push(id);
-                            }
-                        }
-                        ClientTriggerCommand::ReapplyPose { unk1, pose } => {
-                            let msg = FromServer::ActorControl(
-                                from_actor_id,
-                                ActorControl {
-                                    category: ActorControlCategory::Pose {
-                                        unk1: *unk1,
-                                        pose: *pose,
-                                    },
-                                },
-                            );
+                let Some(NetworkedActor::Player(sender_spawn)) =
+                    instance.find_actor(ObjectId(from_actor_id))
+                else {
+                    continue;
+                };
+                let sender_position = sender_spawn.common.pos;
+
+                // everyone else in the instance who is close enough to hear it
+                let recipients: Vec<u32> = instance
+                    .actors
+                    .iter()
+                    .filter_map(|(id, actor)| {
+                        let NetworkedActor::Player(spawn) = actor else {
+                            return None;
+                        };
 
-                            if handle.send(msg).is_err() {
-                                to_remove.push(id);
-                            }
+                        if id.0 == from_actor_id {
+                            return None;
                         }
-                        ClientTriggerCommand::Emote { emote } => {
-                            let msg = FromServer::ActorControl(
-                                from_actor_id,
-                                ActorControl {
-                                    category: ActorControlCategory::Emote { emote: *emote },
-                                },
-                            );
 
-                            if handle.send(msg).is_err() {
-                                to_remove.push(id);
+                        if let Some(range) = range {
+                            if spawn.common.pos.distance(&sender_position) > range {
+                                return None;
                             }
                         }
-                        ClientTriggerCommand::ToggleWeapon { shown } => {
-                            let msg = FromServer::ActorControl(
-                                from_actor_id,
-                                ActorControl {
-                                    category: ActorControlCategory::ToggleWeapon { shown: *shown },
-                                },
-                            );
 
-                            if handle.send(msg).is_err() {
-                                to_remove.push(id);
-                            }
-                        }
-                        _ => tracing::warn!("Server doesn't know what to do with {:#?}", trigger),
+                        Some(id.0)
+                    })
+                    .collect();
+
+                // TODO: use the real chat packets once their opcodes and layouts are known, so the client shows them in the right channel
+                let msg = FromServer::Message(format_chat_message(channel, &sender_name, &message));
+
+                for actor_id in recipients {
+                    if let Some(client_id) = data.find_client_by_actor(actor_id) {
+                        data.send_to_client(client_id, msg.clone());
                     }
                 }
             }
+            ToServer::ActorMoved(from_id, actor_id, position, rotation) => {
+                let mut data = data.lock().unwrap();
+
+                let Some(instance_id) = data.find_actor_instance(actor_id) else {
+                    continue;
+                };
+
+                if let Some(instance) = data.find_actor_instance_mut(actor_id) {
+                    if let Some(spawn) = instance.find_actor_mut(ObjectId(actor_id)) {
+                        let common = match spawn {
+                            NetworkedActor::Player(npc_spawn) => &mut npc_spawn.common,
+                            NetworkedActor::Npc(npc_spawn) => &mut npc_spawn.common,
+                        };
+                        common.pos = position;
+                        common.rotation = rotation;
+                    }
+                }
+
+                let msg = FromServer::ActorMove(actor_id, position, rotation);
+                data.send_to_instance(instance_id, Some(from_id), msg);
+            }
+            ToServer::ClientTrigger(from_id, from_actor_id, trigger) => {
+                let mut data = data.lock().unwrap();
+
+                tracing::info!("{:#?}", trigger);
+
+                // handle player-to-server actions
+                if let ClientTriggerCommand::TeleportQuery { aetheryte_id } = &trigger.trigger {
+                    let msg = FromServer::ActorControlSelf(ActorControlSelf {
+                        category: ActorControlCategory::TeleportStart {
+                            insufficient_gil: 0,
+                            aetheryte_id: *aetheryte_id,
+                        },
+                    });
+
+                    data.send_to_client(from_id, msg);
+                }
+
+                let Some(instance_id) = data.find_actor_instance(from_actor_id) else {
+                    continue;
+                };
+
+                // and then tell everyone else in the instance
+                let msg = match &trigger.trigger {
+                    ClientTriggerCommand::SetTarget { actor_id } => FromServer::ActorControlTarget(
+                        from_actor_id,
+                        ActorControlTarget {
+                            category: ActorControlCategory::SetTarget {
+                                actor_id: *actor_id,
+                            },
+                        },
+                    ),
+                    ClientTriggerCommand::ChangePose { unk1, pose }
+                    | ClientTriggerCommand::ReapplyPose { unk1, pose } => FromServer::ActorControl(
+                        from_actor_id,
+                        ActorControl {
+                            category: ActorControlCategory::Pose {
+                                unk1: *unk1,
+                                pose: *pose,
+                            },
+                        },
+                    ),
+                    ClientTriggerCommand::Emote { emote } => FromServer::ActorControl(
+                        from_actor_id,
+                        ActorControl {
+                            category: ActorControlCategory::Emote { emote: *emote },
+                        },
+                    ),
+                    ClientTriggerCommand::ToggleWeapon { shown } => FromServer::ActorControl(
+                        from_actor_id,
+                        ActorControl {
+                            category: ActorControlCategory::ToggleWeapon { shown: *shown },
+                        },
+                    ),
+                    ClientTriggerCommand::TeleportQuery { .. }
+                    | ClientTriggerCommand::Revive { .. } => continue,
+                    _ => {
+                        tracing::warn!("Server doesn't know what to do with {:#?}", trigger);
+                        continue;
+                    }
+                };
+
+                data.send_to_instance(instance_id, Some(from_id), msg);
+            }
             ToServer::DebugNewNpc(_from_id, from_actor_id) => {
                 let mut data = data.lock().unwrap();
 
-                let actor_id = Instance::generate_actor_id();
+                let Some(instance_id) = data.find_actor_instance(from_actor_id) else {
+                    continue;
+                };
+
+                let actor_id = data.actor_ids.allocate_npc();
                 let spawn;
                 {
-                    let Some(instance) = data.find_actor_instance_mut(from_actor_id) else {
-                        break;
+                    let Some(instance) = data.find_instance_mut(instance_id) else {
+                        continue;
                     };
 
                     let Some(actor) = instance.find_actor(ObjectId(from_actor_id)) else {
-                        break;
+                        continue;
                     };
 
                     let NetworkedActor::Player(player) = actor else {
-                        break;
+                        continue;
                     };
 
                     spawn = NpcSpawn {
@@ -455,6 +1584,7 @@ pub async fn server_main_loop(mut recv: Receiver<ToServer>) -> Result<(), std::i
                 }
 
                 data.send_npc(
+                    instance_id,
                     Actor {
                         id: ObjectId(actor_id),
                         ..Default::default()
@@ -465,19 +1595,22 @@ pub async fn server_main_loop(mut recv: Receiver<ToServer>) -> Result<(), std::i
             ToServer::DebugNewEnemy(_from_id, from_actor_id) => {
                 let mut data = data.lock().unwrap();
 
-                let actor_id = Instance::generate_actor_id();
+                let Some(instance_id) = data.find_actor_instance(from_actor_id) else {
+                    continue;
+                };
+
                 let spawn;
                 {
-                    let Some(instance) = data.find_actor_instance_mut(from_actor_id) else {
-                        break;
+                    let Some(instance) = data.find_instance_mut(instance_id) else {
+                        continue;
                     };
 
                     let Some(actor) = instance.find_actor(ObjectId(from_actor_id)) else {
-                        break;
+                        continue;
                     };
 
                     let NetworkedActor::Player(player) = actor else {
-                        break;
+                        continue;
                     };
 
                     spawn = NpcSpawn {
@@ -499,34 +1632,31 @@ pub async fn server_main_loop(mut recv: Receiver<ToServer>) -> Result<(), std::i
                         },
                         ..Default::default()
                     };
-
-                    instance.insert_npc(ObjectId(actor_id), spawn.clone());
                 }
 
-                data.send_npc(
-                    Actor {
-                        id: ObjectId(actor_id),
-                        ..Default::default()
-                    },
-                    spawn,
-                );
+                let respawn_time = Duration::from_secs(get_config().world.respawn_time);
+                data.spawn_battle_npc(instance_id, spawn, respawn_time);
             }
             ToServer::DebugSpawnClone(_from_id, from_actor_id) => {
                 let mut data = data.lock().unwrap();
 
-                let actor_id = Instance::generate_actor_id();
+                let Some(instance_id) = data.find_actor_instance(from_actor_id) else {
+                    continue;
+                };
+
+                let actor_id = data.actor_ids.allocate_npc();
                 let spawn;
                 {
-                    let Some(instance) = data.find_actor_instance_mut(from_actor_id) else {
-                        break;
+                    let Some(instance) = data.find_instance_mut(instance_id) else {
+                        continue;
                     };
 
                     let Some(actor) = instance.find_actor(ObjectId(from_actor_id)) else {
-                        break;
+                        continue;
                     };
 
                     let NetworkedActor::Player(player) = actor else {
-                        break;
+                        continue;
                     };
 
                     spawn = NpcSpawn {
@@ -539,6 +1669,7 @@ pub async fn server_main_loop(mut recv: Receiver<ToServer>) -> Result<(), std::i
                 }
 
                 data.send_npc(
+                    instance_id,
                     Actor {
                         id: ObjectId(actor_id),
                         ..Default::default()
@@ -547,81 +1678,60 @@ pub async fn server_main_loop(mut recv: Receiver<ToServer>) -> Result<(), std::i
                 );
             }
             ToServer::ActionRequest(from_id, _from_actor_id, request) => {
-                let mut game_data = game_data.lock().unwrap();
-                let cast_time = game_data.get_casttime(request.action_key).unwrap();
+                let cast_time = game_data
+                    .lock()
+                    .unwrap()
+                    .get_casttime(request.action_key)
+                    .unwrap();
 
-                let send_execution = |from_id: ClientId, data: Arc<Mutex<WorldServer>>| {
-                    let mut data = data.lock().unwrap();
-
-                    tracing::info!("Now finishing delayed cast!");
-
-                    for (id, (handle, _)) in &mut data.clients {
-                        let id = *id;
-
-                        if id == from_id {
-                            let msg = FromServer::ActionComplete(request);
-
-                            if handle.send(msg).is_err() {
-                                data.to_remove.push(id);
-                            }
-                            break;
-                        }
-                    }
-                };
+                let mut data = data.lock().unwrap();
 
                 if cast_time == 0 {
                     // If instantaneous, send right back
-                    send_execution(from_id, data.clone());
+                    data.send_to_client(from_id, FromServer::ActionComplete(request));
                 } else {
-                    // Otherwise, delay
-                    // NOTE: I know this won't scale, but it's a fine hack for now
-
+                    // Otherwise, finish it on the tick after the cast time is up
                     tracing::info!(
                         "Delaying spell cast for {} milliseconds",
                         cast_time as u64 * 100
                     );
 
-                    // we have to shadow these variables to tell rust not to move them into the async closure
-                    let data = data.clone();
-                    tokio::task::spawn(async move {
-                        let mut interval =
-                            tokio::time::interval(Duration::from_millis(cast_time as u64 * 100));
-                        interval.tick().await;
-                        interval.tick().await;
-                        send_execution(from_id, data);
-                    });
+                    let finish_at = Instant::now() + Duration::from_millis(cast_time as u64 * 100);
+                    data.pending_casts.push((from_id, request, finish_at));
                 }
             }
+            ToServer::ActionExecuted(_from_id, from_actor_id, request, effects_builder) => {
+                let mut data = data.lock().unwrap();
+
+                data.execute_action(from_actor_id, request, effects_builder);
+            }
             ToServer::Config(_from_id, from_actor_id, config) => {
                 // update their stored state so it's correctly sent on new spawns
                 {
                     let mut data = data.lock().unwrap();
 
                     let Some(instance) = data.find_actor_instance_mut(from_actor_id) else {
-                        break;
+                        continue;
                     };
 
                     let Some(actor) = instance.find_actor_mut(ObjectId(from_actor_id)) else {
-                        break;
+                        continue;
                     };
 
                     let NetworkedActor::Player(player) = actor else {
-                        break;
+                        continue;
                     };
 
                     player.common.display_flags = config.display_flag;
                 }
 
                 let mut data = data.lock().unwrap();
-                for (id, (handle, _)) in &mut data.clients {
-                    let id = *id;
-
-                    let msg = FromServer::UpdateConfig(from_actor_id, config.clone());
+                let Some(instance_id) = data.find_actor_instance(from_actor_id) else {
+                    continue;
+                };
 
-                    if handle.send(msg).is_err() {
-                        to_remove.push(id);
-                    }
-                }
+                let msg = FromServer::UpdateConfig(from_actor_id, config);
+                data.send_to_instance(instance_id, None, msg);
             }
             ToServer::Equip(_from_id, from_actor_id, main_weapon_id, model_ids) => {
                 // update their stored state so it's correctly sent on new spawns
@@ -629,64 +1739,53 @@ pub async fn server_main_loop(mut recv: Receiver<ToServer>) -> Result<(), std::i
                     let mut data = data.lock().unwrap();
 
                     let Some(instance) = data.find_actor_instance_mut(from_actor_id) else {
-                        break;
+                        continue;
                     };
 
                     let Some(actor) = instance.find_actor_mut(ObjectId(from_actor_id)) else {
-                        break;
+                        continue;
                     };
 
                     let NetworkedActor::Player(player) = actor else {
-                        break;
+                        continue;
                     };
 
                     player.common.main_weapon_model = main_weapon_id;
                     player.common.models = model_ids;
                 }
 
-                // Inform all clients about their new equipped model ids
+                // Inform all clients in the instance about their new equipped model ids
                 let mut data = data.lock().unwrap();
-                for (id, (handle, _)) in &mut data.clients {
-                    let id = *id;
-
-                    let msg = FromServer::ActorEquip(from_actor_id, main_weapon_id, model_ids);
+                let Some(instance_id) = data.find_actor_instance(from_actor_id) else {
+                    continue;
+                };
 
-                    if handle.send(msg).is_err() {
-                        to_remove.push(id);
-                    }
-                }
+                let msg = FromServer::ActorEquip(from_actor_id, main_weapon_id, model_ids);
+                data.send_to_instance(instance_id, None, msg);
             }
             ToServer::Disconnected(from_id) => {
                 let mut data = data.lock().unwrap();
 
                 data.to_remove.push(from_id);
             }
-            ToServer::FatalError(err) => return Err(err),
-        }
+            ToServer::Rcon(command, reply) => {
+                let mut data = data.lock().unwrap();
 
-        // Remove any clients that errored out
-        {
-            let mut data = data.lock().unwrap();
-            data.to_remove.append(&mut to_remove);
-
-            for remove_id in data.to_remove.clone() {
-                // remove any actors they had
-                let mut actor_id = None;
-                for (id, (handle, _)) in &mut data.clients {
-                    if *id == remove_id {
-                        actor_id = Some(handle.actor_id);
-                    }
-                }
+                let _ = reply.send(data.run_rcon_command(command));
+            }
+            ToServer::Shutdown(reply) => {
+                let mut data = data.lock().unwrap();
 
-                if let Some(actor_id) = actor_id {
-                    // remove them from the instance
-                    let current_instance = data.find_actor_instance_mut(actor_id).unwrap();
-                    current_instance.actors.remove(&ObjectId(actor_id));
-                }
+                tracing::info!("Disconnecting {} player(s)...", data.clients.len());
 
-                data.clients.remove(&remove_id);
+                data.shutdown = Some(reply);
+                data.send_to_all(FromServer::Shutdown);
             }
+            ToServer::FatalError(err) => return Err(err),
         }
+
+        // Remove any clients that errored out
+        data.lock().unwrap().remove_dead_clients();
     }
     Ok(())
 }
@@ -14,6 +14,32 @@ impl StatusEffects {
         self.dirty = true
     }
 
+    /// Same as `add`, but also records who gave it to us.
+    pub fn add_from(&mut self, effect_id: u16, duration: f32, source_actor_id: u32) {
+        let status_effect = self.find_or_create_status_effect(effect_id);
+        status_effect.duration = duration;
+        status_effect.source_actor_id = source_actor_id;
+        self.dirty = true
+    }
+
+    /// Counts down the effects by `elapsed` seconds, and removes the ones that ran out. Effects with no duration never run out.
+    pub fn tick(&mut self, elapsed: f32) {
+        let count = self.status_effects.len();
+
+        self.status_effects.retain_mut(|effect| {
+            if effect.duration <= 0.0 {
+                return true;
+            }
+
+            effect.duration -= elapsed;
+            effect.duration > 0.0
+        });
+
+        if self.status_effects.len() != count {
+            self.dirty = true;
+        }
+    }
+
     fn find_or_create_status_effect(&mut self, effect_id: u16) -> &mut StatusEffect {
         if let Some(i) = self
             .status_effects
@@ -2,12 +2,18 @@ use icarus::TerritoryType::TerritoryTypeSheet;
 use physis::{
     common::Language,
     layer::{
-        ExitRangeInstanceObject, InstanceObject, LayerEntryData, LayerGroup, PopRangeInstanceObject,
+        BNPCInstanceObject, ENPCInstanceObject, ExitRangeInstanceObject, InstanceObject,
+        LayerEntryData, LayerGroup, PopRangeInstanceObject,
     },
 };
 
 use crate::common::{GameData, TerritoryNameKind};
 
+/// TerritoryIntendedUse of inn rooms.
+pub const INTENDED_USE_INN: u8 = 2;
+/// TerritoryIntendedUse of the opening areas new characters start in.
+pub const INTENDED_USE_OPENING: u8 = 6;
+
 /// Represents a loaded zone
 #[derive(Default, Debug)]
 pub struct Zone {
@@ -135,4 +141,33 @@ impl Zone {
 
         None
     }
+
+    /// Every object in the layer groups NPCs are placed in.
+    fn npc_layer_objects(&self) -> impl Iterator<Item = &InstanceObject> {
+        [&self.planevent, &self.planmap, &self.planlive]
+            .into_iter()
+            .flatten()
+            .flat_map(|lgb| &lgb.chunks[0].layers)
+            .flat_map(|layer| &layer.objects)
+    }
+
+    /// Lists the event NPCs (e.g. shopkeepers and quest givers) placed in this zone.
+    pub fn event_npcs(&self) -> Vec<(&InstanceObject, &ENPCInstanceObject)> {
+        self.npc_layer_objects()
+            .filter_map(|object| match &object.data {
+                LayerEntryData::EventNPC(enpc) => Some((object, enpc)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Lists the battle NPC spawn points placed in this zone.
+    pub fn battle_npcs(&self) -> Vec<(&InstanceObject, &BNPCInstanceObject)> {
+        self.npc_layer_objects()
+            .filter_map(|object| match &object.data {
+                LayerEntryData::BattleNPC(bnpc) => Some((object, bnpc)),
+                _ => None,
+            })
+            .collect()
+    }
 }